@@ -0,0 +1,378 @@
+//! Compiles statements into a flat list of instructions for the stack machine
+//! in `Interpreter::run`. The sheet is evaluated again on every edit, and
+//! `Cache` keeps the lines that didn't change compiled, so they aren't lexed
+//! and parsed again. That's what makes it fast: running the instructions
+//! takes about as long as walking the tree did.
+//!
+//! Instructions are small: tokens, names, units and spans live in pools on
+//! the `Chunk` and instructions refer to them by index. Each instruction
+//! works out one kind of expression, with its operands already on the stack.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::calc::error::{Error, ErrorKind, Span};
+use crate::calc::lexer::{Lexer, Token, TokenKind};
+use crate::calc::locale::Separators;
+use crate::calc::number::Notation;
+use crate::calc::parser::{Aggregate, Atom, Expression, Parser};
+use crate::calc::references;
+use crate::calc::units::{Quantity, Unit};
+use crate::calc::value::{Context, Definition, Value};
+use crate::calc::zone::Zone;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    /// Pushes `constants[index]`.
+    Constant(usize),
+    /// Pushes what `names[name]` stands for.
+    Load { name: usize, span: usize },
+    /// Binds `names[name]` to the value on top, leaving it there.
+    Store(usize),
+    /// Binds `names[name]` to `functions[function]`.
+    Define { name: usize, function: usize },
+    Unary { operator: usize, right: usize },
+    Binary { operator: usize, left: usize, right: usize },
+    /// Jumps to `target` if the value on top is as truthy as `when`,
+    /// leaving it as the result. Otherwise pops it.
+    JumpIf { when: bool, target: usize },
+    /// Calls `names[name]` with the values on top. The call is at
+    /// `spans[span]`, followed by the spans of its arguments.
+    Call { name: usize, arguments: usize, span: usize },
+    /// Converts the value on top to `units[unit]`. The conversion is at
+    /// `spans[span]`, followed by the span of the value.
+    Convert { unit: usize, span: usize },
+    /// Defines the exchange rate `rates[rate]`.
+    Rate { rate: usize, span: usize },
+    Percent { value: usize },
+    /// Takes the value as a percentage, of a whole on top of it if there is
+    /// one.
+    AsPercent { value: usize, whole: Option<usize>, percent: usize },
+    AsFraction { notation: Notation, value: usize },
+    MonthDay { month: u32, day: u32, span: usize },
+    /// Counts `units[unit]` until the date on top, or since it.
+    Elapsed { unit: usize, until: bool, date: usize, span: usize },
+    Weekday { date: usize },
+    /// Moves the value on top into `zones[zone]`, or out of it.
+    Rezone { zone: usize, into: bool, value: usize },
+    Aggregate { aggregate: Aggregate, name: usize, span: usize },
+    PercentChange { from: usize, to: usize, span: usize },
+    /// Fails with `errors[index]`.
+    Fail(usize),
+}
+
+/// A compiled statement and the pools its instructions refer to.
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub operators: Vec<Token>,
+    pub units: Vec<Unit>,
+    pub rates: Vec<(Quantity, Quantity)>,
+    pub zones: Vec<Zone>,
+    pub spans: Vec<Span>,
+    pub errors: Vec<Error>,
+    pub functions: Vec<Rc<Definition>>,
+}
+
+/// Compiles a statement. Running the chunk leaves the value of the
+/// statement, or nothing for a function definition.
+pub fn compile(statement: &Expression) -> Chunk {
+    let mut chunk = Chunk::default();
+
+    match statement {
+        Expression::None => {}
+        Expression::Function(name, parameters, body) => {
+            let name = chunk.name(name);
+            let function = chunk.functions.len();
+            chunk.functions.push(Rc::new(Definition::new(parameters.clone(), body.as_ref().clone())));
+
+            chunk.code.push(Instruction::Define { name, function });
+        }
+        expression => chunk.expression(expression),
+    }
+
+    chunk
+}
+
+impl Chunk {
+    /// Whether the statement only repeats the value of another line, like
+    /// `prev` or `line3 in km`.
+    pub fn is_reference(&self) -> bool {
+        match self.code.as_slice() {
+            [Instruction::Load { name, .. }, conversions @ ..] => {
+                references::is_reference(&self.names[*name])
+                    && conversions.iter().all(|instruction| matches!(instruction, Instruction::Convert { .. }))
+            }
+            _ => false,
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        let instruction = match expression {
+            Expression::Literal(Atom::Name(name), span) => Instruction::Load { name: self.name(name), span: self.span(*span) },
+            Expression::Literal(atom, span) => match Value::from_atom(atom) {
+                Some(value) => Instruction::Constant(self.constant(value)),
+                None => self.fail(Error::new(ErrorKind::MissingOperand, "expected a value", *span)),
+            },
+            Expression::Group(expression) => return self.expression(expression),
+            Expression::Unary(right, operator) => {
+                self.expression(right);
+                Instruction::Unary { operator: self.operator(operator), right: self.span(right.span()) }
+            }
+            Expression::Binary(left, right, operator) => {
+                self.expression(left);
+                self.expression(right);
+                Instruction::Binary { operator: self.operator(operator), left: self.span(left.span()), right: self.span(right.span()) }
+            }
+            Expression::Logical(left, right, operator) => {
+                self.expression(left);
+
+                let jump = self.code.len();
+                self.code.push(Instruction::JumpIf { when: operator.kind() == TokenKind::Or, target: 0 });
+                self.expression(right);
+
+                let target = self.code.len();
+                self.code[jump] = Instruction::JumpIf { when: operator.kind() == TokenKind::Or, target };
+                return;
+            }
+            Expression::Variable(name, value) => {
+                self.expression(value);
+                Instruction::Store(self.name(name))
+            }
+            Expression::Call(name, arguments, span) => {
+                for argument in arguments {
+                    self.expression(argument);
+                }
+
+                let name = self.name(name);
+                let span = self.span(*span);
+
+                for argument in arguments {
+                    self.spans.push(argument.span());
+                }
+
+                Instruction::Call { name, arguments: arguments.len(), span }
+            }
+            Expression::Conversion(value, unit, unit_span) => {
+                self.expression(value);
+
+                self.units.push(unit.clone());
+                let span = self.span(value.span().to(*unit_span));
+                self.spans.push(value.span());
+
+                Instruction::Convert { unit: self.units.len() - 1, span }
+            }
+            Expression::Rate(from, to, span) => {
+                self.rates.push((from.clone(), to.clone()));
+                Instruction::Rate { rate: self.rates.len() - 1, span: self.span(*span) }
+            }
+            Expression::Percent(value, _) => {
+                self.expression(value);
+                Instruction::Percent { value: self.span(value.span()) }
+            }
+            Expression::AsPercent(value, whole, percent) => {
+                self.expression(value);
+
+                let whole = whole.as_ref().map(|whole| {
+                    self.expression(whole);
+                    self.span(whole.span())
+                });
+
+                Instruction::AsPercent { value: self.span(value.span()), whole, percent: self.span(*percent) }
+            }
+            Expression::AsFraction(value, notation, _) => {
+                self.expression(value);
+                Instruction::AsFraction { notation: *notation, value: self.span(value.span()) }
+            }
+            Expression::MonthDay(month, day, span) => Instruction::MonthDay { month: *month, day: *day, span: self.span(*span) },
+            Expression::Until(unit, date, unit_span) | Expression::Since(unit, date, unit_span) => {
+                self.expression(date);
+
+                self.units.push(unit.clone());
+
+                Instruction::Elapsed {
+                    unit: self.units.len() - 1,
+                    until: matches!(expression, Expression::Until(..)),
+                    date: self.span(date.span()),
+                    span: self.span(unit_span.to(date.span())),
+                }
+            }
+            Expression::Weekday(date, _) => {
+                self.expression(date);
+                Instruction::Weekday { date: self.span(date.span()) }
+            }
+            Expression::InZone(value, zone, _) | Expression::ToZone(value, zone, _) => {
+                self.expression(value);
+
+                self.zones.push(zone.clone());
+
+                Instruction::Rezone {
+                    zone: self.zones.len() - 1,
+                    into: matches!(expression, Expression::ToZone(..)),
+                    value: self.span(value.span()),
+                }
+            }
+            Expression::Aggregate(aggregate, name, span) => Instruction::Aggregate {
+                aggregate: *aggregate,
+                name: self.name(name),
+                span: self.span(*span),
+            },
+            Expression::PercentChange(from, to, span) => {
+                self.expression(from);
+                self.expression(to);
+
+                Instruction::PercentChange { from: self.span(from.span()), to: self.span(to.span()), span: self.span(*span) }
+            }
+            Expression::Invalid(error) => self.fail(error.clone()),
+            Expression::None | Expression::Function(..) => {
+                self.fail(Error::new(ErrorKind::MissingOperand, "expected a value", expression.span()))
+            }
+        };
+
+        self.code.push(instruction);
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Names come up again and again, so each is only kept once.
+    fn name(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|known| known == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        }
+    }
+
+    fn operator(&mut self, operator: &Token) -> usize {
+        self.operators.push(operator.clone());
+        self.operators.len() - 1
+    }
+
+    fn span(&mut self, span: Span) -> usize {
+        self.spans.push(span);
+        self.spans.len() - 1
+    }
+
+    fn fail(&mut self, error: Error) -> Instruction {
+        self.errors.push(error);
+        Instruction::Fail(self.errors.len() - 1)
+    }
+}
+
+/// The compiled statements of the lines of a sheet, kept from one evaluation
+/// of the sheet to the next so only lines that changed are compiled again.
+#[derive(Debug, Default)]
+pub struct Cache {
+    lines: HashMap<String, Line>,
+    /// The lines compiled since the last `sweep`, which are all it keeps.
+    used: HashMap<String, Line>,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    separators: Separators,
+    /// The defined names the line was parsed knowing about.
+    names: Vec<String>,
+    chunks: Rc<[Chunk]>,
+    errors: Vec<Error>,
+}
+
+impl Cache {
+    /// The compiled statements of a line and what's wrong with it. A broken
+    /// statement or part of one still compiles, to code that fails with its
+    /// error, so the rest of the line keeps its value. How a line parses
+    /// depends on which of its words are defined names, so it's only compiled
+    /// again when one of those is defined or forgotten.
+    pub fn compile(&mut self, line: &str, separators: Separators, context: &Context) -> (Rc<[Chunk]>, Vec<Error>) {
+        let tokens = Lexer::with_separators(line.to_string(), separators).tokenize_partial();
+
+        // The parser only looks names up as runs of words in a row, like
+        // `hourly rate`, so those are all the names that can matter.
+        let mut names = vec![];
+
+        for start in 0..tokens.len() {
+            let mut name = String::new();
+
+            for token in &tokens[start..] {
+                let TokenKind::Identifier(word) = token.kind() else {
+                    break;
+                };
+
+                if !name.is_empty() {
+                    name.push(' ');
+                }
+                name += &word;
+
+                if context.contains_key(&name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+
+        let cached = self.used.get(line)
+            .or_else(|| self.lines.get(line))
+            .filter(|cached| cached.separators == separators && cached.names == names)
+            .cloned();
+
+        let cached = cached.unwrap_or_else(|| {
+            let (statements, errors) = Parser::with_names(tokens, names.iter().cloned().collect()).parse_partial();
+            let chunks = statements.iter().map(compile).collect();
+
+            Line { separators, names, chunks, errors }
+        });
+
+        let compiled = (cached.chunks.clone(), cached.errors.clone());
+        self.used.insert(line.to_string(), cached);
+
+        compiled
+    }
+
+    /// Forgets the lines that weren't compiled since the last sweep, once
+    /// the whole sheet has been.
+    pub fn sweep(&mut self) {
+        self.lines = std::mem::take(&mut self.used);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::currency::Rates;
+    use crate::calc::interpreter::Interpreter;
+    use crate::calc::number::Number;
+
+    #[test]
+    fn broken_lines_still_define_names() {
+        let mut cache = Cache::default();
+        let mut interpreter = Interpreter::with_rates(Rates::default());
+        let mut context = Context::new();
+
+        let mut evaluate = |line: &str| {
+            let (chunks, errors) = cache.compile(line, Separators::default(), &context);
+            let values = chunks.iter().map(|chunk| interpreter.run(chunk, &mut context)).collect::<Vec<_>>();
+
+            (values, errors.len())
+        };
+
+        let (values, errors) = evaluate("x: (1 + 2");
+        assert_eq!((values, errors), (vec![Ok(Some(Value::Number(Number::from(3))))], 1));
+
+        let (values, errors) = evaluate("x * 2");
+        assert_eq!((values, errors), (vec![Ok(Some(Value::Number(Number::from(6))))], 0));
+
+        // The broken statement fails, but the one after it still runs.
+        let (values, errors) = evaluate("1 + @\ny: x + 1");
+        assert!(values[0].is_err());
+        assert_eq!((&values[1..], errors), (&[Ok(Some(Value::Number(Number::from(4))))][..], 1));
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::calc::lexer::Lexer;
+use crate::calc::number::Number;
+use crate::calc::parser::{Expression, Parser};
+
+/// ISO code, symbols and spelled out names of each known currency.
+const CURRENCIES: &[(&str, &[&str], &[&str])] = &[
+    ("USD", &["$"], &["dollar", "dollars"]),
+    ("EUR", &["€"], &["euro", "euros"]),
+    ("GBP", &["£"], &[]),
+    ("JPY", &["¥"], &["yen"]),
+    ("CNY", &[], &["yuan"]),
+    ("INR", &["₹"], &["rupee", "rupees"]),
+    ("KRW", &["₩"], &[]),
+    ("RUB", &["₽"], &[]),
+    ("TRY", &["₺"], &[]),
+    ("ILS", &["₪"], &[]),
+    ("VND", &["₫"], &[]),
+    ("THB", &["฿"], &[]),
+    ("PHP", &["₱"], &[]),
+    ("BRL", &[], &[]),
+    ("CHF", &[], &[]),
+    ("CAD", &[], &[]),
+    ("AUD", &[], &[]),
+    ("NZD", &[], &[]),
+    ("MXN", &[], &[]),
+    ("SEK", &[], &[]),
+    ("NOK", &[], &[]),
+    ("DKK", &[], &[]),
+    ("PLN", &[], &[]),
+    ("CZK", &[], &[]),
+    ("HUF", &[], &[]),
+    ("ZAR", &[], &[]),
+    ("SGD", &[], &[]),
+    ("HKD", &[], &[]),
+];
+
+/// Returns the ISO code for a currency code or symbol, like `EUR` or `€`.
+pub fn lookup(text: &str) -> Option<&'static str> {
+    CURRENCIES.iter()
+        .find(|(code, symbols, _)| *code == text || symbols.contains(&text))
+        .map(|(code, _, _)| *code)
+}
+
+/// Returns the ISO code for a spelled out currency, like `euros`.
+pub fn lookup_name(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+
+    CURRENCIES.iter()
+        .find(|(_, _, names)| names.contains(&name.as_str()))
+        .map(|(code, _, _)| *code)
+}
+
+/// Exchange rates between currencies, as defined by `1 USD = 0.92 EUR` lines
+/// in a sheet or in the rates file.
+#[derive(Debug, Clone, Default)]
+pub struct Rates {
+    rates: HashMap<(String, String), Number>,
+}
+
+impl Rates {
+    /// Reads rates from a file using the same `1 USD = 0.92 EUR` syntax as
+    /// sheets, ignoring any line that isn't a rate.
+    pub fn load(path: &Path) -> std::io::Result<Rates> {
+        let mut rates = Rates::default();
+
+        for line in std::fs::read_to_string(path)?.lines() {
+            let Ok(tokens) = Lexer::new(line.to_string()).tokenize() else {
+                continue;
+            };
+
+            let Ok(expressions) = Parser::new(tokens).parse() else {
+                continue;
+            };
+
+            for expression in expressions {
+                if let Expression::Rate(from, to, _) = expression {
+                    let codes = (from.unit().currency(), to.unit().currency());
+
+                    if let (Some(from_code), Some(to_code), Some(rate)) = (codes.0, codes.1, to.value().checked_div(from.value())) {
+                        rates.insert(from_code, to_code, rate);
+                    }
+                }
+            }
+        }
+
+        Ok(rates)
+    }
+
+    /// Records that one unit of `from` is worth `rate` units of `to`.
+    pub fn insert(&mut self, from: &str, to: &str, rate: Number) {
+        self.rates.insert((from.to_string(), to.to_string()), rate);
+    }
+
+    /// How many units of `to` one unit of `from` is worth, going through the
+    /// inverse rate or a third currency when there's no direct one.
+    pub fn rate(&self, from: &str, to: &str) -> Option<Number> {
+        if from == to {
+            return Some(Number::from(1));
+        }
+
+        if let Some(rate) = self.direct_rate(from, to) {
+            return Some(rate);
+        }
+
+        self.rates.keys()
+            .flat_map(|(a, b)| [a, b])
+            .find_map(|via| Some(self.direct_rate(from, via)? * self.direct_rate(via, to)?))
+    }
+
+    fn direct_rate(&self, from: &str, to: &str) -> Option<Number> {
+        let key = (from.to_string(), to.to_string());
+        let inverse = (to.to_string(), from.to_string());
+
+        self.rates.get(&key).cloned()
+            .or_else(|| Number::from(1).checked_div(self.rates.get(&inverse)?))
+    }
+}
@@ -0,0 +1,353 @@
+use std::fmt;
+
+use crate::calc::number::Number;
+use crate::calc::units::{Quantity, Unit};
+use crate::calc::zone::Zone;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// How many days from 1970 dates can be, about a million years either way,
+/// which keeps the arithmetic on them from overflowing.
+const MAX_DAYS: i64 = 365_000_000;
+
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june",
+    "july", "august", "september", "october", "november", "december",
+];
+
+/// Names that stand for a date relative to now.
+pub const KEYWORDS: &[&str] = &["now", "today", "tomorrow", "yesterday"];
+
+const WEEKDAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+/// A calendar date, optionally with a time of day, in local wall clock time.
+///
+/// It's stored as seconds since 1970-01-01 00:00 as if every day had exactly
+/// 86400 seconds, which keeps arithmetic on dates simple and exact.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Date {
+    seconds: i64,
+    has_time: bool,
+}
+
+impl Date {
+    /// Returns `None` for dates that don't exist, like `2026-02-30`.
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Date> {
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        Some(Date { seconds: days_from_civil(year, month, day) * SECONDS_PER_DAY, has_time: false })
+    }
+
+    /// Returns `None` for dates too far from 1970.
+    fn from_seconds(seconds: i64, has_time: bool) -> Option<Date> {
+        if seconds.div_euclid(SECONDS_PER_DAY).abs() > MAX_DAYS {
+            return None;
+        }
+
+        Some(Date { seconds, has_time })
+    }
+
+    /// The current local date and time.
+    pub fn now() -> Date {
+        // SAFETY: `localtime_r` only writes to the `tm` we hand it.
+        let seconds = unsafe {
+            let time = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = std::mem::zeroed();
+
+            if libc::localtime_r(&time, &mut tm).is_null() {
+                time
+            } else {
+                time + tm.tm_gmtoff
+            }
+        };
+
+        Date { seconds, has_time: true }
+    }
+
+    /// The same day without the time of day.
+    pub fn date(&self) -> Date {
+        Date { seconds: self.seconds.div_euclid(SECONDS_PER_DAY) * SECONDS_PER_DAY, has_time: false }
+    }
+
+    pub fn has_time(&self) -> bool {
+        self.has_time
+    }
+
+    /// Returns `None` when the date would be out of range.
+    pub fn add_days(&self, days: i64) -> Option<Date> {
+        let seconds = days.checked_mul(SECONDS_PER_DAY)?.checked_add(self.seconds)?;
+
+        Date::from_seconds(seconds, self.has_time)
+    }
+
+    /// Moves by whole months, keeping the day where possible, so a month
+    /// after January 31 is the last day of February. Returns `None` when the
+    /// date would be out of range.
+    pub fn add_months(&self, months: i64) -> Option<Date> {
+        let (year, month, day) = self.ymd();
+        let index = (year * 12 + (month as i64 - 1)).checked_add(months)?;
+        let (year, month) = (index.div_euclid(12), index.rem_euclid(12) as u32 + 1);
+
+        // Out of range anyway, and too far out for `days_from_civil`.
+        if year.abs() > MAX_DAYS / 365 + 1970 {
+            return None;
+        }
+
+        let day = day.min(days_in_month(year, month));
+        let time = self.seconds.rem_euclid(SECONDS_PER_DAY);
+
+        Date::from_seconds(days_from_civil(year, month, day) * SECONDS_PER_DAY + time, self.has_time)
+    }
+
+    /// Adds a duration like `3 weeks`. Months and years move along the
+    /// calendar, anything else is added as an exact number of seconds.
+    /// Returns `None` when the quantity isn't a duration or the date would be
+    /// out of range.
+    pub fn add(&self, duration: &Quantity) -> Option<Date> {
+        let months = match duration.unit().to_string().as_str() {
+            "month" => Some(1),
+            "year" => Some(12),
+            _ => None,
+        };
+
+        if let Some(months) = months {
+            if duration.value().is_integer() {
+                let count = duration.value().to_f64();
+
+                if count.abs() > MAX_DAYS as f64 {
+                    return None;
+                }
+
+                return self.add_months(count as i64 * months);
+            }
+        }
+
+        let seconds = duration.convert(&Unit::lookup("s")?)?.value().to_f64().round();
+
+        // Twice the range covers moving from one end of it to the other.
+        if !seconds.is_finite() || seconds.abs() > (2 * MAX_DAYS * SECONDS_PER_DAY) as f64 {
+            return None;
+        }
+
+        let seconds = seconds as i64;
+        let has_time = self.has_time || seconds % SECONDS_PER_DAY != 0;
+
+        Date::from_seconds(self.seconds.checked_add(seconds)?, has_time)
+    }
+
+    /// How long it is from `other` to `self`, in days.
+    pub fn since(&self, other: &Date) -> Quantity {
+        let seconds = Number::from(self.seconds - other.seconds);
+        let days = seconds.checked_div(&Number::from(SECONDS_PER_DAY)).unwrap_or_default();
+
+        Quantity::new(days, Unit::lookup("day").unwrap_or_default())
+    }
+
+    /// The next time it's `month` and `day`, counting `today` itself.
+    pub fn upcoming(month: u32, day: u32, today: &Date) -> Option<Date> {
+        let (year, _, _) = today.ymd();
+
+        match Date::from_ymd(year, month, day) {
+            Some(date) if date >= today.date() => Some(date),
+            _ => Date::from_ymd(year + 1, month, day),
+        }
+    }
+
+    pub fn ymd(&self) -> (i64, u32, u32) {
+        civil_from_days(self.seconds.div_euclid(SECONDS_PER_DAY))
+    }
+
+    /// The same day at a time of day.
+    pub fn at(&self, time: &Time) -> Date {
+        Date { seconds: self.date().seconds + time.seconds, has_time: true }
+    }
+
+    /// The same moment on the clocks of another time zone.
+    pub fn convert(&self, from: &Zone, to: &Zone) -> Date {
+        let utc = from.to_utc(self.seconds);
+
+        Date { seconds: utc + to.offset_at(utc), has_time: true }
+    }
+
+    pub fn weekday(&self) -> &'static str {
+        // 1970-01-01 was a Thursday.
+        let days = self.seconds.div_euclid(SECONDS_PER_DAY);
+        WEEKDAYS[(days + 3).rem_euclid(7) as usize]
+    }
+}
+
+/// Written as `2026-12-25`, or `2026-12-25 09:30` with a time of day.
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{year:04}-{month:02}-{day:02}")?;
+
+        if self.has_time {
+            let time = self.seconds.rem_euclid(SECONDS_PER_DAY);
+            write!(f, " {:02}:{:02}", time / 3600, time % 3600 / 60)?;
+
+            if time % 60 != 0 {
+                write!(f, ":{:02}", time % 60)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A time of day without a date, like `17:45` or `9:30am`.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Time {
+    /// Since midnight, always less than a day.
+    seconds: i64,
+    /// Whether it was written with `am` or `pm`, and should be shown that way.
+    twelve_hour: bool,
+}
+
+impl Time {
+    /// Returns `None` for times that don't exist, like `24:00` or `9:60`.
+    pub fn from_hms(hour: u32, minute: u32, second: u32) -> Option<Time> {
+        if hour >= 24 || minute >= 60 || second >= 60 {
+            return None;
+        }
+
+        let seconds = i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second);
+
+        Some(Time { seconds, twelve_hour: false })
+    }
+
+    /// A time on the 12-hour clock, where `12am` is midnight and `12pm` noon.
+    pub fn from_twelve_hour(hour: u32, minute: u32, second: u32, pm: bool) -> Option<Time> {
+        if !(1..=12).contains(&hour) {
+            return None;
+        }
+
+        let hour = hour % 12 + if pm { 12 } else { 0 };
+
+        Some(Time { twelve_hour: true, ..Time::from_hms(hour, minute, second)? })
+    }
+
+    /// Moves the clock by a duration, going around past midnight. Returns
+    /// `None` when the quantity isn't a duration.
+    pub fn add(&self, duration: &Quantity) -> Option<Time> {
+        // Whole days don't move the clock, and taking them off first keeps
+        // huge durations from overflowing.
+        let seconds = duration.convert(&Unit::lookup("s")?)?.value()
+            .checked_rem(&Number::from(SECONDS_PER_DAY))?
+            .to_f64()
+            .round() as i64;
+
+        Some(Time { seconds: (self.seconds + seconds).rem_euclid(SECONDS_PER_DAY), ..*self })
+    }
+
+    /// The same moment on the clocks of another time zone, on `day`, which
+    /// matters when only one of the zones has daylight saving time.
+    pub fn convert(&self, day: &Date, from: &Zone, to: &Zone) -> Time {
+        let moment = day.at(self).convert(from, to);
+
+        Time { seconds: moment.seconds.rem_euclid(SECONDS_PER_DAY), ..*self }
+    }
+
+    /// How many seconds it is from `other` to `self` on the same day.
+    pub fn since(&self, other: &Time) -> Number {
+        Number::from(self.seconds - other.seconds)
+    }
+}
+
+/// Written as `17:45`, or as `5:45pm` when it was given on the 12-hour clock.
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (hour, minute, second) = (self.seconds / 3600, self.seconds % 3600 / 60, self.seconds % 60);
+
+        match self.twelve_hour {
+            true => write!(f, "{}:{minute:02}", (hour + 11) % 12 + 1)?,
+            false => write!(f, "{hour:02}:{minute:02}")?,
+        }
+
+        if second != 0 {
+            write!(f, ":{second:02}")?;
+        }
+
+        if self.twelve_hour {
+            write!(f, "{}", if hour < 12 { "am" } else { "pm" })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a number of seconds the way durations are typed in, like `2h 15m`
+/// or `1d 4h 30s`.
+pub fn format_duration(seconds: &Number, precision: usize) -> String {
+    let sign = if *seconds < Number::default() { "-" } else { "" };
+    let seconds = seconds.abs();
+
+    let whole = seconds.floor(0).unwrap_or_default();
+    let fraction = seconds - whole.clone();
+    let whole = whole.to_f64() as i64;
+
+    let mut parts: Vec<String> = [(whole / SECONDS_PER_DAY, "d"), (whole % SECONDS_PER_DAY / 3600, "h"), (whole % 3600 / 60, "m")]
+        .into_iter()
+        .filter(|(count, _)| *count != 0)
+        .map(|(count, suffix)| format!("{count}{suffix}"))
+        .collect();
+
+    if whole % 60 != 0 || !fraction.is_zero() || parts.is_empty() {
+        let seconds = Number::from(whole % 60) + fraction;
+        parts.push(format!("{seconds:.precision$}s"));
+    }
+
+    format!("{sign}{}", parts.join(" "))
+}
+
+/// The number of a month from its name or the first three letters of it,
+/// like `December` or `dec`.
+pub fn month(name: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+
+    MONTHS.iter()
+        .position(|month| *month == name || (name.len() == 3 && month.starts_with(&name)))
+        .map(|index| index as u32 + 1)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar, after Howard
+/// Hinnant's `days_from_civil`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
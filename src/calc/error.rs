@@ -0,0 +1,79 @@
+use std::fmt;
+
+/// Where a token or expression came from in the evaluated input.
+///
+/// `start` and `end` are byte offsets, `line` and `column` are zero-based and
+/// point at the first character.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self { start, end, line, column }
+    }
+
+    /// Returns a span covering both `self` and `other`.
+    pub fn to(&self, other: Span) -> Span {
+        if other.start < self.start {
+            return other.to(*self);
+        }
+
+        Span { end: self.end.max(other.end), ..*self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    InvalidToken,
+    UnexpectedToken,
+    MissingOperand,
+    UnknownName,
+    UnknownUnit,
+    TypeMismatch,
+    DimensionMismatch,
+    MissingRate,
+    DivisionByZero,
+    ArityMismatch,
+    RecursionLimit,
+    OutOfDomain,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+    span: Span,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>, span: Span) -> Self {
+        Self { kind, message: message.into(), span }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line + 1, self.span.column + 1, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,160 @@
+use std::fmt;
+
+use crate::calc::number::Number;
+
+/// How many arguments a function accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    Between(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exactly(n) => count == n,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+            Arity::AtLeast(min) => count >= min,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exactly(1) => write!(f, "1 argument"),
+            Arity::Exactly(n) => write!(f, "{n} arguments"),
+            Arity::Between(min, max) if max - min == 1 => write!(f, "{min} or {max} arguments"),
+            Arity::Between(min, max) => write!(f, "{min} to {max} arguments"),
+            Arity::AtLeast(1) => write!(f, "at least 1 argument"),
+            Arity::AtLeast(min) => write!(f, "at least {min} arguments"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Implementation {
+    /// Works on exact numbers and decides for itself when to fall back to
+    /// floating point.
+    Exact(fn(&[Number]) -> Option<Number>),
+    /// Only has a floating point answer, like `sin`.
+    Float(fn(f64) -> f64),
+}
+
+/// A function built into every sheet, like `sqrt` or `max`.
+pub struct Function {
+    pub name: &'static str,
+    pub arity: Arity,
+    /// Whether a quantity keeps its unit, so `round(2.345 m, 1)` is `2.3 m`.
+    /// Functions without it only take plain numbers.
+    pub keeps_unit: bool,
+    /// Whether the arguments have to be whole numbers, like for `gcd`.
+    pub whole: bool,
+    implementation: Implementation,
+}
+
+impl Function {
+    /// Returns `None` when the function isn't defined for the arguments, like
+    /// `sqrt(-1)` or `asin(2)`.
+    pub fn apply(&self, arguments: &[Number]) -> Option<Number> {
+        match self.implementation {
+            Implementation::Exact(apply) => apply(arguments),
+            Implementation::Float(apply) => Number::float(apply(arguments.first()?.to_f64())),
+        }
+    }
+}
+
+const fn exact(name: &'static str, arity: Arity, apply: fn(&[Number]) -> Option<Number>) -> Function {
+    Function { name, arity, keeps_unit: false, whole: false, implementation: Implementation::Exact(apply) }
+}
+
+const fn float(name: &'static str, apply: fn(f64) -> f64) -> Function {
+    Function { name, arity: Arity::Exactly(1), keeps_unit: false, whole: false, implementation: Implementation::Float(apply) }
+}
+
+const fn unit_preserving(name: &'static str, arity: Arity, apply: fn(&[Number]) -> Option<Number>) -> Function {
+    Function { keeps_unit: true, ..exact(name, arity, apply) }
+}
+
+const fn whole(name: &'static str, apply: fn(&[Number]) -> Option<Number>) -> Function {
+    Function { whole: true, ..exact(name, Arity::Exactly(2), apply) }
+}
+
+const FUNCTIONS: &[Function] = &[
+    // Roots and magnitudes
+    exact("sqrt", Arity::Exactly(1), |x| x[0].sqrt()),
+    exact("hypot", Arity::Exactly(2), |x| (x[0].clone() * x[0].clone() + x[1].clone() * x[1].clone()).sqrt()),
+    unit_preserving("abs", Arity::Exactly(1), |x| Some(x[0].abs())),
+
+    // Rounding, with an optional number of decimal places
+    unit_preserving("round", Arity::Between(1, 2), |x| x[0].round(places(x)?)),
+    unit_preserving("floor", Arity::Between(1, 2), |x| x[0].floor(places(x)?)),
+    unit_preserving("ceil", Arity::Between(1, 2), |x| x[0].ceil(places(x)?)),
+
+    // Comparisons
+    unit_preserving("min", Arity::AtLeast(1), |x| x.iter().cloned().reduce(|a, b| if b < a { b } else { a })),
+    unit_preserving("max", Arity::AtLeast(1), |x| x.iter().cloned().reduce(|a, b| if b > a { b } else { a })),
+    unit_preserving("clamp", Arity::Exactly(3), clamp),
+
+    // Logarithms, `log` is base 10 unless given a base
+    exact("log", Arity::Between(1, 2), log),
+    float("ln", f64::ln),
+    float("exp", f64::exp),
+
+    // Trigonometry, in radians
+    float("sin", f64::sin),
+    float("cos", f64::cos),
+    float("tan", f64::tan),
+    float("asin", f64::asin),
+    float("acos", f64::acos),
+    float("atan", f64::atan),
+    float("sinh", f64::sinh),
+    float("cosh", f64::cosh),
+    float("tanh", f64::tanh),
+    float("asinh", f64::asinh),
+    float("acosh", f64::acosh),
+    float("atanh", f64::atanh),
+
+    // Integers
+    whole("gcd", |x| x[0].gcd(&x[1])),
+    whole("lcm", |x| x[0].lcm(&x[1])),
+];
+
+/// Returns the built-in function called `name`.
+pub fn lookup(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+/// The optional decimal places argument of the rounding functions.
+fn places(arguments: &[Number]) -> Option<i32> {
+    match arguments.get(1) {
+        Some(places) => places.to_i32(),
+        None => Some(0),
+    }
+}
+
+fn clamp(arguments: &[Number]) -> Option<Number> {
+    let [value, low, high] = arguments else {
+        return None;
+    };
+
+    if low > high {
+        return None;
+    }
+
+    match value {
+        _ if value < low => Some(low.clone()),
+        _ if value > high => Some(high.clone()),
+        _ => Some(value.clone()),
+    }
+}
+
+fn log(arguments: &[Number]) -> Option<Number> {
+    let value = arguments[0].to_f64();
+
+    match arguments.get(1) {
+        Some(base) => Number::float(value.ln() / base.to_f64().ln()),
+        None => Number::float(value.log10()),
+    }
+}
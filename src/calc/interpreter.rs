@@ -1,212 +1,1146 @@
-use std::collections::HashMap;
+use crate::calc::compiler::{Chunk, Instruction};
+use crate::calc::currency::Rates;
+use crate::calc::date::Date;
+use crate::calc::error::{Error, ErrorKind, Result, Span};
+use crate::calc::functions::{self, Arity};
+use crate::calc::lexer::{Token, TokenKind};
+use crate::calc::number::{Notation, Number};
+use crate::calc::parser::Aggregate;
+use crate::calc::references;
+use crate::calc::units::{Quantity, Unit};
+use crate::calc::value::{Binding, Context, Definition, Value};
+use crate::calc::zone::Zone;
 
-use crate::calc::lexer::TokenKind;
-use crate::calc::parser::{Expression, Atom};
+/// How deep user-defined functions may call each other before giving up, so
+/// a recursive function without a base case fails instead of hanging.
+const MAX_CALL_DEPTH: usize = 64;
 
-pub struct Interpreter;
+pub struct Interpreter {
+    rates: Rates,
+    depth: usize,
+    /// The parameters of the function being called, which its body sees
+    /// before the names of the sheet. A function doesn't see the parameters
+    /// of the one that called it.
+    locals: Context,
+    /// Taken once so every line of a sheet agrees on what `now` is.
+    now: Date,
+    /// The zone dates and times are in unless they say otherwise.
+    zone: Zone,
+    /// Values of the lines since the last blank line, for `sum` and the
+    /// other aggregates.
+    block: Vec<Value>,
+    /// Values of all lines, for `grand total`.
+    sheet: Vec<Value>,
+    /// Whether the current line doesn't count towards the next aggregate:
+    /// an aggregate itself, an exchange rate, or a line that only refers to
+    /// another one and would count it twice.
+    uncounted: bool,
+}
 
 impl Interpreter {
-    pub fn new() -> Self {
-        Self {}
-    }
-
-    pub fn evaluate(&mut self, expression: Expression, context: &mut HashMap<String, Expression>) -> Expression {
-        match expression {
-            Expression::Literal(ref value) => match value {
-                Atom::Name(variable_name) => {
-                    match context.get(variable_name) {
-                        Some(name) => name.clone(),
-                        None => {
-                            // println!("name {} not found", variable_name);
-                            Expression::None
-                        },
+    pub fn with_rates(rates: Rates) -> Self {
+        Self {
+            rates,
+            depth: 0,
+            locals: Context::new(),
+            now: Date::now(),
+            zone: Zone::local(),
+            block: vec![],
+            sheet: vec![],
+            uncounted: false,
+        }
+    }
+
+    /// Takes note of what a line of the sheet evaluated to, once all of it
+    /// has been evaluated. Only numbers and quantities are aggregated.
+    pub fn record_line(&mut self, value: Option<&Value>) {
+        if self.uncounted {
+            self.uncounted = false;
+            return;
+        }
+
+        let value = match value {
+            Some(Value::Fraction(number, _)) => Value::Number(number.clone()),
+            Some(value @ (Value::Number(_) | Value::Quantity(_) | Value::Duration(_))) => value.clone(),
+            _ => return,
+        };
+
+        self.block.push(value.clone());
+        self.sheet.push(value);
+    }
+
+    /// Starts a new block for the aggregates, at a blank line.
+    pub fn end_block(&mut self) {
+        self.block.clear();
+    }
+
+    /// What a name stands for, in the function being called or else in the
+    /// sheet.
+    fn binding<'a>(&'a self, name: &str, context: &'a Context) -> Option<&'a Binding> {
+        self.locals.get(name).or_else(|| context.get(name))
+    }
+
+    fn lookup(&self, name: &str, span: Span, context: &Context) -> Result<Value> {
+        match self.binding(name, context) {
+            Some(Binding::Value(value)) => return Ok(value.clone()),
+            Some(Binding::Function(_)) => {
+                return Err(Error::new(ErrorKind::TypeMismatch, format!("'{name}' is a function"), span));
+            }
+            None => {}
+        }
+
+        if let Some(date) = self.date_keyword(name) {
+            return Ok(Value::Date(date));
+        }
+
+        // Names that aren't variables can still be units, e.g. the `h` in
+        // `100 km / h`.
+        if let Some(unit) = Unit::lookup(name) {
+            return Ok(Value::Quantity(Quantity::new(Number::from(1), unit)));
+        }
+
+        let message = match references::line_number(name) {
+            Some(line) => format!("line {line} has no value"),
+            None if references::PREVIOUS.contains(&name) => "no line above has a value".to_string(),
+            None => format!("unknown name '{name}'"),
+        };
+
+        Err(Error::new(ErrorKind::UnknownName, message, span))
+    }
+
+    /// Calls a function defined in the sheet. Its body is run with only its
+    /// parameters as locals, which stand for the arguments, and the names of
+    /// the sheet are left as they were.
+    ///
+    /// Errors in the body point into the line that defined the function, so
+    /// they're moved onto the call, which is on the line being evaluated.
+    fn call_defined(&mut self, name: &str, definition: &Definition, arguments: Vec<(Value, Span)>, span: Span, context: &mut Context) -> Result<Value> {
+        if definition.parameters.len() != arguments.len() {
+            return Err(Error::new(
+                ErrorKind::ArityMismatch,
+                format!("'{name}' expects {}, got {}", Arity::Exactly(definition.parameters.len()), arguments.len()),
+                span,
+            ));
+        }
+
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(Error::new(ErrorKind::RecursionLimit, format!("'{name}' recursed too deeply"), span));
+        }
+
+        let locals = definition.parameters.iter()
+            .zip(arguments)
+            .map(|(parameter, (value, _))| (parameter.clone(), Binding::Value(value)))
+            .collect();
+        let caller = std::mem::replace(&mut self.locals, locals);
+
+        self.depth += 1;
+        let result = self.run(&definition.chunk, context)
+            .and_then(|value| value.ok_or_else(|| Error::new(ErrorKind::MissingOperand, "expected a value", span)));
+        self.depth -= 1;
+
+        self.locals = caller;
+
+        result.map_err(|error| {
+            // Only the outermost call says which function it was, or a
+            // recursive one would say it again for every level.
+            let message = match self.depth {
+                0 => format!("in '{name}': {}", error.message()),
+                _ => error.message().to_string(),
+            };
+
+            Error::new(error.kind(), message, span)
+        })
+    }
+
+    /// Calls a built-in function, for names that the sheet hasn't defined a
+    /// function of its own for.
+    fn call_builtin(&self, name: &str, arguments: Vec<(Value, Span)>, span: Span) -> Result<Value> {
+        let Some(function) = functions::lookup(name) else {
+            return Err(Error::new(ErrorKind::UnknownName, format!("unknown function '{name}'"), span));
+        };
+
+        if !function.arity.accepts(arguments.len()) {
+            return Err(Error::new(
+                ErrorKind::ArityMismatch,
+                format!("'{name}' expects {}, got {}", function.arity, arguments.len()),
+                span,
+            ));
+        }
+
+        // Quantities are all converted to the unit of the first one, and the
+        // result gets that unit back.
+        let mut unit: Option<Unit> = None;
+        let mut numbers = vec![];
+
+        for (argument, argument_span) in arguments {
+            let number = match argument {
+                Value::Number(number) => number,
+                Value::Percentage(percentage) => percentage.shift(-2),
+                Value::Quantity(quantity) if function.keeps_unit => {
+                    let target = unit.get_or_insert_with(|| quantity.unit().clone());
+
+                    match quantity.convert(target) {
+                        Some(quantity) => quantity.value().clone(),
+                        None => return Err(Error::new(
+                            ErrorKind::DimensionMismatch,
+                            format!("incompatible units {} and {}", target, quantity.unit()),
+                            argument_span,
+                        )),
                     }
                 }
-                _ => expression,
+                Value::Quantity(_) => {
+                    return Err(Error::new(ErrorKind::TypeMismatch, format!("'{name}' expects a plain number"), argument_span));
+                }
+                _ => return Err(Error::new(ErrorKind::TypeMismatch, "expected a number", argument_span)),
+            };
+
+            if function.whole && !number.is_integer() {
+                return Err(Error::new(ErrorKind::TypeMismatch, format!("'{name}' expects whole numbers"), argument_span));
             }
-            Expression::Group(expression) => {
-                self.evaluate(*expression.clone(), context)
+
+            numbers.push(number);
+        }
+
+        let Some(result) = function.apply(&numbers) else {
+            return Err(Error::new(ErrorKind::OutOfDomain, format!("'{name}' is undefined for these arguments"), span));
+        };
+
+        match unit {
+            Some(unit) => self.quantity(Quantity::new(result, unit), span),
+            None => Ok(Value::Number(result)),
+        }
+    }
+
+    fn convert(&self, value: Value, unit: &Unit, value_span: Span, span: Span) -> Result<Value> {
+        match self.seconds(value) {
+            Value::Quantity(quantity) => match self.exchange(quantity.clone(), unit, span)?.convert(unit) {
+                Some(quantity) => Ok(Value::Quantity(quantity)),
+                None => Err(Error::new(
+                    ErrorKind::DimensionMismatch,
+                    format!("cannot convert {} to {}", quantity.unit(), unit),
+                    span,
+                )),
+            },
+            // A plain number is taken to already be in the target unit.
+            Value::Number(number) => Ok(Value::Quantity(Quantity::new(number, unit.clone()))),
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a number", value_span)),
+        }
+    }
+
+    fn rate(&mut self, from: &Quantity, to: &Quantity, span: Span) -> Result<Value> {
+        self.uncounted = true;
+
+        if let (Some(from_code), Some(to_code)) = (from.unit().currency(), to.unit().currency()) {
+            let rate = self.divide(to.value(), from.value(), span)?;
+            self.rates.insert(from_code, to_code, rate);
+        }
+
+        Ok(Value::Quantity(to.clone()))
+    }
+
+    fn percent(&self, value: Value, span: Span) -> Result<Value> {
+        match value {
+            Value::Number(number) => Ok(Value::Percentage(number)),
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a number", span)),
+        }
+    }
+
+    /// `0.25 as %`, or `30 as % of 120` with a whole.
+    fn as_percent(&self, ratio: Value, span: Span, whole: Option<(Value, Span)>, percent_span: Span) -> Result<Value> {
+        let ratio = match whole {
+            Some((whole, whole_span)) => {
+                let over = Token::new(TokenKind::Over, "/", percent_span);
+                self.binary(&over, ratio, whole, span, whole_span)?
             }
-            // Expression::Unary(right, operator) => {
-            //     let right_value = self.evaluate(*right.clone(), context);
+            None => ratio,
+        };
+
+        self.percentage(ratio, span.to(percent_span))
+    }
+
+    fn as_fraction(&self, value: Value, notation: Notation, span: Span) -> Result<Value> {
+        match value {
+            Value::Number(number) => Ok(Value::Fraction(number, notation)),
+            Value::Percentage(percentage) => Ok(Value::Fraction(percentage.shift(-2), notation)),
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a number", span)),
+        }
+    }
+
+    fn month_day(&self, month: u32, day: u32, span: Span) -> Result<Value> {
+        match Date::upcoming(month, day, &self.now) {
+            Some(date) => Ok(Value::Date(date)),
+            None => Err(Error::new(ErrorKind::OutOfDomain, "no such date", span)),
+        }
+    }
+
+    fn weekday(&self, value: Value, span: Span) -> Result<Value> {
+        let date = self.date(value, span)?;
+
+        Ok(Value::Text(date.weekday().to_string()))
+    }
+
+    /// A line of just `total` or `avg`, unless the sheet has a variable of
+    /// that name.
+    fn aggregate_line(&mut self, aggregate: Aggregate, name: &str, span: Span, context: &Context) -> Result<Value> {
+        if let Some(Binding::Value(value)) = self.binding(name, context) {
+            return Ok(value.clone());
+        }
+
+        self.uncounted = true;
+
+        let values = match aggregate {
+            Aggregate::GrandTotal => self.sheet.clone(),
+            _ => self.block.clone(),
+        };
+
+        self.aggregate(aggregate, name, values, span)
+    }
 
-            //     match operator.kind() {
-                    // TokenKind::Not => {
-                    //     let result = self.is_truthy(self.literal_value(right_value).unwrap());
-                    //     Expression::Literal(Atom::Boolean(!result))
-                    // }
-                    // TokenKind::Minus => {
-                    //     let result = self.literal_value(right_value).unwrap();
-                    //     match result {
-                    //         Atom::Number(x) => Expression::Literal(Atom::Number(-x)),
-                    //         _ => Expression::None,
-                    //     }
+    fn percent_change(&self, x: Value, y: Value, from: Span, to: Span, span: Span) -> Result<Value> {
+        let minus = Token::new(TokenKind::Minus, "-", span);
+        let over = Token::new(TokenKind::Over, "/", span);
 
-                    // }
-            //         _ => Expression::None
-            //     }
-            // }
-            Expression::Binary(left, right, operator) => {
-                let left_expr = self.evaluate(*left.clone(), context);
-                let right_expr = self.evaluate(*right.clone(), context);
+        let difference = self.binary(&minus, y, x.clone(), to, from)?;
+        let ratio = self.binary(&over, difference, x, to, from)?;
 
-                if let None = self.literal_value(left_expr.clone()) {
-                    return Expression::None;
+        self.percentage(ratio, span)
+    }
+
+    /// Runs a compiled statement on a stack, with the same result as
+    /// evaluating the statement it was compiled from.
+    pub fn run(&mut self, chunk: &Chunk, context: &mut Context) -> Result<Option<Value>> {
+        let mut stack: Vec<Value> = vec![];
+        let mut next = 0;
+
+        if chunk.is_reference() {
+            self.uncounted = true;
+        }
+
+        while let Some(instruction) = chunk.code.get(next) {
+            next += 1;
+
+            let value = match *instruction {
+                Instruction::Constant(index) => chunk.constants[index].clone(),
+                Instruction::Load { name, span } => self.lookup(&chunk.names[name], chunk.spans[span], context)?,
+                Instruction::Store(name) => {
+                    let scope = match self.depth {
+                        0 => &mut *context,
+                        _ => &mut self.locals,
+                    };
+
+                    if let Some(value) = stack.last() {
+                        scope.insert(chunk.names[name].clone(), Binding::Value(value.clone()));
+                    }
+
+                    continue;
+                }
+                Instruction::Define { name, function } => {
+                    context.insert(chunk.names[name].clone(), Binding::Function(chunk.functions[function].clone()));
+                    continue;
                 }
+                Instruction::Unary { operator, right } => {
+                    let value = operand(&mut stack, chunk.spans[right])?;
+                    self.unary(&chunk.operators[operator], value, chunk.spans[right])?
+                }
+                Instruction::Binary { operator, left, right } => {
+                    let y = operand(&mut stack, chunk.spans[right])?;
+                    let x = operand(&mut stack, chunk.spans[left])?;
+                    self.binary(&chunk.operators[operator], x, y, chunk.spans[left], chunk.spans[right])?
+                }
+                Instruction::JumpIf { when, target } => {
+                    match stack.last() {
+                        Some(value) if self.is_truthy(value) == when => next = target,
+                        _ => {
+                            stack.pop();
+                        }
+                    }
 
-                if let None = self.literal_value(right_expr.clone()) {
-                    return Expression::None;
+                    continue;
                 }
+                Instruction::Call { name, arguments, span } => {
+                    let spans = &chunk.spans[span + 1..=span + arguments];
 
-                let left_value = self.literal_value(left_expr).unwrap();
-                let right_value = self.literal_value(right_expr).unwrap();
+                    let Some(start) = stack.len().checked_sub(arguments) else {
+                        return Err(Error::new(ErrorKind::MissingOperand, "expected a value", chunk.spans[span]));
+                    };
 
-                match operator.kind() {
-                    TokenKind::Plus => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+                    let values = stack.split_off(start).into_iter()
+                        .map(plain)
+                        .zip(spans.iter().copied())
+                        .collect();
 
-                            return Expression::Literal(Atom::Number(x + y));
-                        }
+                    let name = &chunk.names[name];
+
+                    match self.binding(name, context).cloned() {
+                        Some(Binding::Function(definition)) => self.call_defined(name, &definition, values, chunk.spans[span], context)?,
+                        _ => self.call_builtin(name, values, chunk.spans[span])?,
+                    }
+                }
+                Instruction::Convert { unit, span } => {
+                    let value = operand(&mut stack, chunk.spans[span + 1])?;
+                    self.convert(value, &chunk.units[unit], chunk.spans[span + 1], chunk.spans[span])?
+                }
+                Instruction::Rate { rate, span } => {
+                    let (from, to) = &chunk.rates[rate];
+                    self.rate(from, to, chunk.spans[span])?
+                }
+                Instruction::Percent { value } => {
+                    let operand = operand(&mut stack, chunk.spans[value])?;
+                    self.percent(operand, chunk.spans[value])?
+                }
+                Instruction::AsPercent { value, whole, percent } => {
+                    let whole = match whole {
+                        Some(whole) => Some((operand(&mut stack, chunk.spans[whole])?, chunk.spans[whole])),
+                        None => None,
+                    };
+                    let ratio = operand(&mut stack, chunk.spans[value])?;
+                    self.as_percent(ratio, chunk.spans[value], whole, chunk.spans[percent])?
+                }
+                Instruction::AsFraction { notation, value } => {
+                    let operand = operand(&mut stack, chunk.spans[value])?;
+                    self.as_fraction(operand, notation, chunk.spans[value])?
+                }
+                Instruction::MonthDay { month, day, span } => self.month_day(month, day, chunk.spans[span])?,
+                Instruction::Elapsed { unit, until, date, span } => {
+                    let date = self.date(operand(&mut stack, chunk.spans[date])?, chunk.spans[date])?;
 
-                        Expression::None
-                    },
-                    TokenKind::Minus => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+                    match until {
+                        true => self.elapsed(self.start_of(&date), date, &chunk.units[unit], chunk.spans[span])?,
+                        false => self.elapsed(date, self.start_of(&date), &chunk.units[unit], chunk.spans[span])?,
+                    }
+                }
+                Instruction::Weekday { date } => self.weekday(operand(&mut stack, chunk.spans[date])?, chunk.spans[date])?,
+                Instruction::Rezone { zone, into, value } => {
+                    let operand = operand(&mut stack, chunk.spans[value])?;
 
-                            return Expression::Literal(Atom::Number(x - y));
-                        }
+                    match into {
+                        true => self.rezone(operand, chunk.spans[value], &self.zone, &chunk.zones[zone])?,
+                        false => self.rezone(operand, chunk.spans[value], &chunk.zones[zone], &self.zone)?,
+                    }
+                }
+                Instruction::Aggregate { aggregate, name, span } => {
+                    self.aggregate_line(aggregate, &chunk.names[name], chunk.spans[span], context)?
+                }
+                Instruction::PercentChange { from, to, span } => {
+                    let y = operand(&mut stack, chunk.spans[to])?;
+                    let x = operand(&mut stack, chunk.spans[from])?;
+                    self.percent_change(x, y, chunk.spans[from], chunk.spans[to], chunk.spans[span])?
+                }
+                Instruction::Fail(index) => return Err(chunk.errors[index].clone()),
+            };
 
-                        Expression::None
-                    },
-                    TokenKind::Times => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+            stack.push(value);
+        }
 
-                            return Expression::Literal(Atom::Number(x * y));
-                        }
+        Ok(stack.pop())
+    }
 
-                        Expression::None
-                    },
-                    TokenKind::Over => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+    fn aggregate(&self, aggregate: Aggregate, name: &str, values: Vec<Value>, span: Span) -> Result<Value> {
+        let count = Value::Number(Number::from(values.len() as i64));
 
-                            return Expression::Literal(Atom::Number(x / y));
-                        }
+        let mut values = values.into_iter();
 
-                        Expression::None
-                    },
-                    TokenKind::Less => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+        let Some(mut result) = values.next() else {
+            return match aggregate {
+                Aggregate::Sum | Aggregate::GrandTotal | Aggregate::Count => Ok(Value::Number(Number::default())),
+                _ => Err(Error::new(ErrorKind::MissingOperand, format!("no values above to take the {name} of"), span)),
+            };
+        };
 
-                            return Expression::Literal(Atom::Boolean(x < y));
-                        }
+        if aggregate == Aggregate::Count {
+            return Ok(count);
+        }
 
-                        Expression::None
-                    },
-                    TokenKind::LessEqual => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+        // The operators point their errors at the whole line.
+        for value in values {
+            result = match aggregate {
+                Aggregate::Min | Aggregate::Max => {
+                    let kind = if aggregate == Aggregate::Min { TokenKind::Less } else { TokenKind::Greater };
 
-                            return Expression::Literal(Atom::Boolean(x <= y));
-                        }
+                    match self.binary(&Token::new(kind, name, span), value.clone(), result.clone(), span, span)? {
+                        Value::Boolean(true) => value,
+                        _ => result,
+                    }
+                }
+                _ => self.binary(&Token::new(TokenKind::Plus, "+", span), result, value, span, span)?,
+            };
+        }
 
-                        Expression::None
-                    },
-                    TokenKind::Greater => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+        if aggregate == Aggregate::Average {
+            return self.binary(&Token::new(TokenKind::Over, "/", span), result, count, span, span);
+        }
 
-                            return Expression::Literal(Atom::Boolean(x > y));
-                        }
+        Ok(result)
+    }
 
-                        Expression::None
-                    },
-                    TokenKind::GreaterEqual => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+    fn date_keyword(&self, name: &str) -> Option<Date> {
+        match name {
+            "now" => Some(self.now),
+            "today" => Some(self.now.date()),
+            "tomorrow" => self.now.date().add_days(1),
+            "yesterday" => self.now.date().add_days(-1),
+            _ => None,
+        }
+    }
 
-                            return Expression::Literal(Atom::Boolean(x >= y));
-                        }
+    fn date(&self, value: Value, span: Span) -> Result<Date> {
+        match value {
+            Value::Date(date) => Ok(date),
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a date", span)),
+        }
+    }
 
-                        Expression::None
-                    },
-                    TokenKind::Equal => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+    /// What `days until` counts from: today for plain dates, and the current
+    /// time when a time of day is involved.
+    fn start_of(&self, date: &Date) -> Date {
+        match date.has_time() {
+            true => self.now,
+            false => self.now.date(),
+        }
+    }
 
-                            return Expression::Literal(Atom::Boolean(x == y));
-                        }
+    fn elapsed(&self, from: Date, to: Date, unit: &Unit, span: Span) -> Result<Value> {
+        match to.since(&from).convert(unit) {
+            Some(quantity) => self.quantity(quantity, span),
+            None => Err(Error::new(ErrorKind::DimensionMismatch, format!("cannot count dates in {unit}"), span)),
+        }
+    }
 
-                        Expression::None
-                    },
-                    TokenKind::Mod => {
-                        if let Some(_) = left_value.number() {
-                            let x = left_value.number().unwrap();
-                            let y = right_value.number().unwrap();
+    /// Dates move by durations and subtract into one. Any other arithmetic
+    /// on them is a mistake.
+    fn date_arithmetic(&self, operator: &Token, x: Value, y: Value, span: Span) -> Result<Value> {
+        let kind = operator.kind();
 
-                            return Expression::Literal(Atom::Number(x % y));
-                        }
+        let (date, duration) = match (x, y) {
+            (Value::Date(x), Value::Date(y)) => {
+                let result = match kind {
+                    TokenKind::Minus => return Ok(Value::Quantity(x.since(&y))),
+                    TokenKind::Equal => x == y,
+                    TokenKind::NotEqual => x != y,
+                    TokenKind::Less => x < y,
+                    TokenKind::LessEqual => x <= y,
+                    TokenKind::Greater => x > y,
+                    TokenKind::GreaterEqual => x >= y,
+                    _ => return Err(Error::new(ErrorKind::TypeMismatch, format!("cannot use '{}' on two dates", operator.lexeme()), span)),
+                };
+
+                return Ok(Value::Boolean(result));
+            }
+            (Value::Date(date), Value::Quantity(duration)) | (Value::Quantity(duration), Value::Date(date)) if kind == TokenKind::Plus => {
+                (date, duration)
+            }
+            (Value::Date(date), Value::Quantity(duration)) if kind == TokenKind::Minus => {
+                (date, Quantity::new(-duration.value().clone(), duration.unit().clone()))
+            }
+            _ => return Err(Error::new(ErrorKind::TypeMismatch, "dates can only be moved by a duration, like 3 days", span)),
+        };
+
+        if !duration.unit().is_time() {
+            return Err(Error::new(ErrorKind::DimensionMismatch, "dates can only be moved by a duration, like 3 days", span));
+        }
+
+        match date.add(&duration) {
+            Some(date) => Ok(Value::Date(date)),
+            None => Err(Error::new(ErrorKind::OutOfDomain, "the date is out of range", span)),
+        }
+    }
+
+    /// Moves a date or time from the clocks of one zone to another. A time
+    /// on its own is taken to be today.
+    fn rezone(&self, value: Value, span: Span, from: &Zone, to: &Zone) -> Result<Value> {
+        match value {
+            Value::Date(date) => Ok(Value::Date(date.convert(from, to))),
+            Value::Time(time) => Ok(Value::Time(time.convert(&self.now.date(), from, to))),
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a date or time", span)),
+        }
+    }
+
+    /// Clock times move by durations and subtract into one, and a date plus
+    /// a time is that moment.
+    fn time_arithmetic(&self, operator: &Token, x: Value, y: Value, span: Span) -> Result<Value> {
+        let kind = operator.kind();
+
+        let shifted = match (x, y) {
+            (Value::Time(x), Value::Time(y)) => {
+                let result = match kind {
+                    TokenKind::Minus => return Ok(Value::Duration(x.since(&y))),
+                    TokenKind::Equal => x == y,
+                    TokenKind::NotEqual => x != y,
+                    TokenKind::Less => x < y,
+                    TokenKind::LessEqual => x <= y,
+                    TokenKind::Greater => x > y,
+                    TokenKind::GreaterEqual => x >= y,
+                    _ => return Err(Error::new(ErrorKind::TypeMismatch, format!("cannot use '{}' on two times", operator.lexeme()), span)),
+                };
+
+                return Ok(Value::Boolean(result));
+            }
+            (Value::Date(date), Value::Time(time)) | (Value::Time(time), Value::Date(date)) if kind == TokenKind::Plus => {
+                return Ok(Value::Date(date.at(&time)));
+            }
+            (Value::Time(time), Value::Quantity(duration)) | (Value::Quantity(duration), Value::Time(time)) if kind == TokenKind::Plus => {
+                time.add(&duration)
+            }
+            (Value::Time(time), Value::Quantity(duration)) if kind == TokenKind::Minus => {
+                time.add(&Quantity::new(-duration.value().clone(), duration.unit().clone()))
+            }
+            _ => return Err(Error::new(ErrorKind::TypeMismatch, "times can only be moved by a duration, like 2 hours", span)),
+        };
 
-                        Expression::None
-                    },
-                    _ => Expression::None,
+        match shifted {
+            Some(time) => Ok(Value::Time(time)),
+            None => Err(Error::new(ErrorKind::DimensionMismatch, "times can only be moved by a duration, like 2 hours", span)),
+        }
+    }
+
+    /// A duration as a quantity in seconds, for the arithmetic that's shared
+    /// with other quantities.
+    fn seconds(&self, value: Value) -> Value {
+        match value {
+            Value::Duration(seconds) => Value::Quantity(Quantity::new(seconds, Unit::lookup("s").unwrap_or_default())),
+            value => value,
+        }
+    }
+
+    /// Turns a time quantity that came out of duration arithmetic back into
+    /// a duration.
+    fn duration(&self, result: Value) -> Value {
+        match result {
+            Value::Quantity(quantity) if quantity.unit().is_time() => match quantity.base_value() {
+                Some(seconds) => Value::Duration(seconds),
+                None => Value::Quantity(quantity),
+            },
+            result => result,
+        }
+    }
+
+    fn unary(&self, operator: &Token, value: Value, right: Span) -> Result<Value> {
+        let span = operator.span().to(right);
+
+        match (operator.kind(), value) {
+            (TokenKind::Not, value) => Ok(Value::Boolean(!self.is_truthy(&value))),
+            (TokenKind::Plus, value @ (Value::Number(_) | Value::Percentage(_) | Value::Quantity(_) | Value::Duration(_))) => Ok(value),
+            (TokenKind::Minus, Value::Number(number)) => Ok(Value::Number(-number)),
+            (TokenKind::Minus, Value::Percentage(percentage)) => Ok(Value::Percentage(-percentage)),
+            (TokenKind::Minus, Value::Duration(seconds)) => Ok(Value::Duration(-seconds)),
+            (TokenKind::Minus, Value::Quantity(quantity)) => {
+                Ok(Value::Quantity(Quantity::new(-quantity.value().clone(), quantity.unit().clone())))
+            }
+            (TokenKind::Bang, Value::Number(number)) => match number.factorial() {
+                Some(factorial) => Ok(Value::Number(factorial)),
+                None => Err(Error::new(ErrorKind::OutOfDomain, format!("no factorial of {number}"), span)),
+            },
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a number", right)),
+        }
+    }
+
+    fn binary(&self, operator: &Token, x: Value, y: Value, left: Span, right: Span) -> Result<Value> {
+        let span = left.to(right);
+        let kind = operator.kind();
+
+        match (x, y) {
+            // Durations work like any other time quantity, but whatever time
+            // comes out stays in hours and minutes.
+            (x @ Value::Duration(_), y) | (x, y @ Value::Duration(_)) => {
+                let result = self.binary(operator, self.seconds(x), self.seconds(y), left, right)?;
+                Ok(self.duration(result))
+            }
+            (x @ Value::Time(_), y) | (x, y @ Value::Time(_)) => self.time_arithmetic(operator, x, y, span),
+            (x @ Value::Date(_), y) | (x, y @ Value::Date(_)) => self.date_arithmetic(operator, x, y, span),
+            (Value::Boolean(x), Value::Boolean(y)) => match kind {
+                TokenKind::Equal => Ok(Value::Boolean(x == y)),
+                TokenKind::NotEqual | TokenKind::Xor => Ok(Value::Boolean(x != y)),
+                _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a number", left)),
+            },
+            (x, y) if kind == TokenKind::Xor => Ok(Value::Boolean(self.is_truthy(&x) != self.is_truthy(&y))),
+            (Value::Percentage(x), Value::Percentage(y)) if matches!(kind, TokenKind::Plus | TokenKind::Minus) => {
+                match self.apply(operator, x, y, span)? {
+                    Value::Number(value) => Ok(Value::Percentage(value)),
+                    result => Ok(result),
                 }
             }
-            Expression::Logical(left, right, operator) => {
-                let left_value = self.evaluate(*left.clone(), context);
+            // `80 + 15%` adds 15% of 80
+            (x @ (Value::Number(_) | Value::Quantity(_)), Value::Percentage(percentage)) if matches!(kind, TokenKind::Plus | TokenKind::Minus) => {
+                let part = self.scale(&x, &percentage.shift(-2));
+                self.binary(operator, x, part, left, right)
+            }
+            (Value::Percentage(percentage), y @ (Value::Number(_) | Value::Quantity(_))) if kind == TokenKind::Of => {
+                Ok(self.scale(&y, &percentage.shift(-2)))
+            }
+            // Anywhere else a percentage is just a fraction.
+            (Value::Percentage(percentage), y) => self.binary(operator, Value::Number(percentage.shift(-2)), y, left, right),
+            (x, Value::Percentage(percentage)) => self.binary(operator, x, Value::Number(percentage.shift(-2)), left, right),
+            (Value::Number(x), Value::Number(y)) => self.apply(operator, x, y, span),
+            (Value::Quantity(x), Value::Number(y)) if kind == TokenKind::Caret => self.power(x, y, span),
+            (Value::Number(_), Value::Quantity(_)) | (Value::Quantity(_), Value::Quantity(_)) if kind == TokenKind::Caret => {
+                Err(Error::new(ErrorKind::TypeMismatch, "the exponent must be a plain number", right))
+            }
+            (Value::Quantity(x), Value::Quantity(y)) => self.apply_quantities(operator, x, y, span),
+            (Value::Quantity(x), Value::Number(y)) => {
+                let y = self.promote(operator, y, x.unit());
+                self.apply_quantities(operator, x, y, span)
+            }
+            (Value::Number(x), Value::Quantity(y)) => {
+                let x = self.promote(operator, x, y.unit());
+                self.apply_quantities(operator, x, y, span)
+            }
+            (Value::Number(_) | Value::Quantity(_), _) => {
+                Err(Error::new(ErrorKind::TypeMismatch, "expected a number", right))
+            }
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a number", left)),
+        }
+    }
 
-                if operator.kind() == TokenKind::Or {
-                    if self.is_truthy(self.literal_value(*left.clone()).unwrap()) {
-                        return left_value;
-                    }
-                } else {
-                    if !self.is_truthy(self.literal_value(*left.clone()).unwrap()) {
-                        return left_value;
-                    }
+    fn scale(&self, value: &Value, factor: &Number) -> Value {
+        match value {
+            Value::Number(number) => Value::Number(number.clone() * factor.clone()),
+            Value::Quantity(quantity) => Value::Quantity(Quantity::new(quantity.value().clone() * factor.clone(), quantity.unit().clone())),
+            _ => value.clone(),
+        }
+    }
+
+    fn percentage(&self, ratio: Value, span: Span) -> Result<Value> {
+        match ratio {
+            Value::Number(ratio) => Ok(Value::Percentage(ratio.shift(2))),
+            Value::Percentage(_) => Ok(ratio),
+            Value::Quantity(quantity) => Err(Error::new(
+                ErrorKind::DimensionMismatch,
+                format!("cannot express {} as a percentage", quantity.unit()),
+                span,
+            )),
+            _ => Err(Error::new(ErrorKind::TypeMismatch, "expected a number", span)),
+        }
+    }
+
+    /// Moves a quantity into the currency of `unit` when both have one, so
+    /// the rest of the conversion only has to deal with fixed factors.
+    fn exchange(&self, quantity: Quantity, unit: &Unit, span: Span) -> Result<Quantity> {
+        let (Some(from), Some(to)) = (quantity.unit().currency(), unit.currency()) else {
+            return Ok(quantity);
+        };
+
+        match self.rates.rate(from, to).and_then(|rate| quantity.exchange(to, &rate)) {
+            Some(quantity) => Ok(quantity),
+            None => Err(Error::new(
+                ErrorKind::MissingRate,
+                format!("no exchange rate from {from} to {to}"),
+                span,
+            )),
+        }
+    }
+
+    fn apply(&self, operator: &Token, x: Number, y: Number, span: Span) -> Result<Value> {
+        match operator.kind() {
+            TokenKind::Plus => Ok(Value::Number(x + y)),
+            TokenKind::Minus => Ok(Value::Number(x - y)),
+            TokenKind::Times => Ok(Value::Number(x * y)),
+            TokenKind::Over => Ok(Value::Number(self.divide(&x, &y, span)?)),
+            TokenKind::Caret => match x.pow(&y) {
+                Some(power) => Ok(Value::Number(power)),
+                None if is_undefined_power(&x, &y) => {
+                    Err(Error::new(ErrorKind::OutOfDomain, format!("{x} to the power of {y} is undefined"), span))
                 }
+                None => Err(Error::new(ErrorKind::OutOfDomain, "the power is too large", span)),
+            },
+            TokenKind::Mod => match x.checked_rem(&y) {
+                Some(remainder) => Ok(Value::Number(remainder)),
+                None => Err(Error::new(ErrorKind::DivisionByZero, "division by zero", span)),
+            },
+            TokenKind::Less => Ok(Value::Boolean(x < y)),
+            TokenKind::LessEqual => Ok(Value::Boolean(x <= y)),
+            TokenKind::Greater => Ok(Value::Boolean(x > y)),
+            TokenKind::GreaterEqual => Ok(Value::Boolean(x >= y)),
+            TokenKind::Equal => Ok(Value::Boolean(x == y)),
+            TokenKind::NotEqual => Ok(Value::Boolean(x != y)),
+            _ => Err(Error::new(
+                ErrorKind::UnexpectedToken,
+                format!("'{}' is not a binary operator", operator.lexeme()),
+                operator.span(),
+            )),
+        }
+    }
+
+    /// Raises a quantity to a whole power, so `(3 m)^2` is `9 m^2`.
+    fn power(&self, x: Quantity, y: Number, span: Span) -> Result<Value> {
+        let Some(power) = y.to_i32() else {
+            return Err(Error::new(ErrorKind::DimensionMismatch, "units can only be raised to whole powers", span));
+        };
+
+        let unit = x.unit().pow(power).ok_or_else(|| exponent_too_large(span))?;
+
+        match x.value().powi(power) {
+            Some(value) => self.quantity(Quantity::new(value, unit), span),
+            None if x.value().is_zero() => Err(Error::new(ErrorKind::DivisionByZero, "division by zero", span)),
+            None => Err(Error::new(ErrorKind::OutOfDomain, "the power is too large", span)),
+        }
+    }
 
-                self.evaluate(*right, context)
+    fn divide(&self, x: &Number, y: &Number, span: Span) -> Result<Number> {
+        match x.checked_div(y) {
+            Some(quotient) => Ok(quotient),
+            None => Err(Error::new(ErrorKind::DivisionByZero, "division by zero", span)),
+        }
+    }
+
+    fn apply_quantities(&self, operator: &Token, x: Quantity, y: Quantity, span: Span) -> Result<Value> {
+        match operator.kind() {
+            TokenKind::Times => {
+                let unit = x.unit().multiply(y.unit()).ok_or_else(|| exponent_too_large(span))?;
+                self.quantity(Quantity::new(x.value().clone() * y.value().clone(), unit), span)
+            }
+            TokenKind::Over => {
+                let unit = x.unit().divide(y.unit()).ok_or_else(|| exponent_too_large(span))?;
+                self.quantity(Quantity::new(self.divide(x.value(), y.value(), span)?, unit), span)
             }
-            Expression::Variable(name, value) => {
-                let expr = self.evaluate(*value, context);
-                context.insert(name.clone(), expr.clone());
+            _ => {
+                // Everything else needs both sides in the same unit, the one on
+                // the left wins.
+                let Some(y) = y.convert(x.unit()) else {
+                    if let (Some(a), Some(b)) = (x.unit().currency(), y.unit().currency()) {
+                        return Err(Error::new(
+                            ErrorKind::DimensionMismatch,
+                            format!("cannot combine {a} and {b}, convert one of them first"),
+                            span,
+                        ));
+                    }
 
-                // let value = match self.literal_value(expr) {
-                //     Some(v) => v,
-                //     None => Atom::Boolean(false),
-                // };
+                    return Err(Error::new(
+                        ErrorKind::DimensionMismatch,
+                        format!("incompatible units {} and {}", x.unit(), y.unit()),
+                        span,
+                    ));
+                };
 
-                Expression::Literal(Atom::Name(name))
+                match self.apply(operator, x.value().clone(), y.value().clone(), span)? {
+                    Value::Number(value) => self.quantity(Quantity::new(value, x.unit().clone()), span),
+                    result => Ok(result),
+                }
             }
-            _ => expression,
         }
     }
 
-    pub fn literal_value(&self, expression: Expression) -> Option<Atom> {
-        match expression {
-            Expression::Literal(value) => Some(value),
-            _ => None,
+    /// Treats a bare number next to a quantity as a scale factor when
+    /// multiplying or dividing, and as an amount of the same unit otherwise,
+    /// so `5 km + 3` is `8 km`.
+    fn promote(&self, operator: &Token, number: Number, unit: &Unit) -> Quantity {
+        match operator.kind() {
+            TokenKind::Times | TokenKind::Over => Quantity::new(number, Unit::default()),
+            _ => Quantity::new(number, unit.clone()),
         }
     }
 
-    fn is_truthy(&self, value: Atom) -> bool {
+    /// Wraps up a quantity, dropping the unit once all dimensions cancel out.
+    fn quantity(&self, quantity: Quantity, span: Span) -> Result<Value> {
+        if !quantity.unit().is_dimensionless() {
+            return Ok(Value::Quantity(quantity));
+        }
+
+        match quantity.base_value() {
+            Some(value) => Ok(Value::Number(value)),
+            None => Err(Error::new(ErrorKind::OutOfDomain, format!("{} is too large to convert", quantity.unit()), span)),
+        }
+    }
+
+    fn is_truthy(&self, value: &Value) -> bool {
         match value {
-            Atom::Boolean(boolean) => boolean,
+            Value::Boolean(boolean) => *boolean,
             _ => true,
         }
     }
 }
 
+/// Pops the operand of an instruction at `span`. Compiled code pushes it
+/// before the instruction that uses it, but a chunk that doesn't fails
+/// rather than panics.
+fn operand(stack: &mut Vec<Value>, span: Span) -> Result<Value> {
+    match stack.pop() {
+        Some(value) => Ok(plain(value)),
+        None => Err(Error::new(ErrorKind::MissingOperand, "expected a value", span)),
+    }
+}
+
+/// A fraction is only a way of showing a number, so operators see the
+/// number itself.
+fn plain(value: Value) -> Value {
+    match value {
+        Value::Fraction(number, _) => Value::Number(number),
+        value => value,
+    }
+}
+
+/// The error for a unit raised past the largest exponent, like `m^256`.
+fn exponent_too_large(span: Span) -> Error {
+    Error::new(ErrorKind::OutOfDomain, "the exponent is too large", span)
+}
+
+/// Whether `x^y` has no real answer, as opposed to one too large to work out.
+fn is_undefined_power(x: &Number, y: &Number) -> bool {
+    let zero = Number::from(0);
+
+    (x.is_zero() && *y < zero) || (*x < zero && !y.is_integer())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::calc::compiler::{self, Cache};
+    use crate::calc::lexer::Lexer;
+    use crate::calc::locale::Separators;
+    use crate::calc::parser::{Expression, Parser};
+
+    /// A sheet with variables, functions, units, percentages, line references
+    /// and a total.
+    const SHEET: &str = "square(x): x * x
+hypot(a, b): sqrt(square(a) + square(b))
+
+width: 2 m + 40 cm
+box height: 2 * width - 15%
+area(scale): width * box height * scale
+area(2) in cm^2
+12 km / 3 h in m/s
+3 + 1 <= 2 or 3 != 3
+hypot(3, 4) ^ 2 - square(3)
+(1 + 2 * 3 - 4 / 5) ^ 2 mod 7
+20% of 300 + line4 / 1 m
+prev * 3 + 1 > 10 and not false
+
+rent: 1200
+food: 400 + 15%
+total";
+
+    /// Each line of a sheet parsed, knowing the names defined above it like
+    /// the window does.
+    fn parse(sheet: &str) -> Vec<Vec<Expression>> {
+        let mut names = HashSet::new();
+
+        sheet.split('\n')
+            .map(|line| {
+                let tokens = Lexer::new(line.to_string()).tokenize().unwrap_or_default();
+                let statements = Parser::with_names(tokens, names.clone()).parse().unwrap_or_default();
+
+                for statement in &statements {
+                    if let Expression::Variable(name, _) | Expression::Function(name, _, _) = statement {
+                        names.insert(name.clone());
+                    }
+                }
+
+                statements
+            })
+            .collect()
+    }
+
+    /// Evaluates a parsed sheet the way the window does, with `evaluate`
+    /// giving the value of a statement by its line and index.
+    fn sheet(lines: &[Vec<Expression>], mut evaluate: impl FnMut(&mut Interpreter, (usize, usize), &mut Context) -> Result<Option<Value>>) -> Vec<Result<Option<Value>>> {
+        let mut interpreter = Interpreter::with_rates(Rates::default());
+        let mut context = Context::new();
+        let mut results = vec![];
+
+        for (index, statements) in lines.iter().enumerate() {
+            if statements.is_empty() {
+                interpreter.end_block();
+            }
+
+            let mut value = Ok(None);
+
+            for statement in 0..statements.len() {
+                value = evaluate(&mut interpreter, (index, statement), &mut context);
+
+                if value.is_err() {
+                    break;
+                }
+            }
+
+            interpreter.record_line(value.as_ref().ok().and_then(Option::as_ref));
+
+            if let Ok(Some(value)) = &value {
+                references::record(&mut context, index, value.clone());
+            }
+
+            results.push(value);
+        }
+
+        results
+    }
+
+    fn run(lines: &[Vec<Expression>], chunks: &[Vec<Chunk>]) -> Vec<Result<Option<Value>>> {
+        sheet(lines, |interpreter, (line, statement), context| interpreter.run(&chunks[line][statement], context))
+    }
+
+    fn compile(lines: &[Vec<Expression>]) -> Vec<Vec<Chunk>> {
+        lines.iter().map(|statements| statements.iter().map(compiler::compile).collect()).collect()
+    }
+
+    /// Evaluates a sheet like the window does, with its lines compiled by
+    /// `cache`, which keeps them for the next time.
+    fn cached(sheet: &str, lines: &[Vec<Expression>], cache: &mut Cache) -> Vec<Result<Option<Value>>> {
+        let sheet: Vec<_> = sheet.split('\n').collect();
+        let mut chunks: Rc<[Chunk]> = Rc::from([]);
+
+        let results = self::sheet(lines, |interpreter, (line, statement), context| {
+            if statement == 0 {
+                chunks = cache.compile(sheet[line], Separators::default(), context).0;
+            }
+
+            interpreter.run(&chunks[statement], context)
+        });
+
+        cache.sweep();
+        results
+    }
+
+    /// A result like the window shows it, or the message of its error.
+    fn show(result: &Result<Option<Value>>) -> String {
+        match result {
+            Ok(None) => String::new(),
+            Ok(Some(Value::Boolean(boolean))) => boolean.to_string(),
+            Ok(Some(Value::Number(number))) => number.to_string(),
+            Ok(Some(Value::Quantity(quantity))) => quantity.to_string(),
+            Ok(Some(Value::Date(date))) => date.to_string(),
+            Ok(Some(Value::Time(time))) => time.to_string(),
+            Ok(Some(value)) => format!("{value:?}"),
+            Err(error) => error.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn sheets() {
+        let lines = parse(SHEET);
+        let results = run(&lines, &compile(&lines));
+
+        let shown: Vec<_> = results.iter().map(show).collect();
+        assert_eq!(shown, [
+            "", "", "",
+            "2.4 m", "4.08 m", "", "195840 cm^2", "1.1111111111 m/s", "false", "16", "3.44", "62.4", "true",
+            "",
+            "1200", "460", "1660",
+        ]);
+
+        // Lines the cache compiled before run the same as new ones.
+        let mut cache = Cache::default();
+        assert_eq!(results, cached(SHEET, &lines, &mut cache));
+        assert_eq!(results, cached(SHEET, &lines, &mut cache));
+    }
+
+    #[test]
+    fn totals_skip_rates_and_references() {
+        let lines = parse("1 USD = 0.92 EUR\n10 EUR\n5 USD in EUR\nprev\nline2 in EUR\ntotal\n\n3 EUR\nsum\ngrand total");
+        let results = run(&lines, &compile(&lines));
+
+        let shown: Vec<_> = results.iter().map(show).collect();
+        assert_eq!(shown, ["0.92 EUR", "10.00 EUR", "4.60 EUR", "4.60 EUR", "10.00 EUR", "14.60 EUR", "", "3.00 EUR", "3.00 EUR", "17.60 EUR"]);
+    }
+
+    #[test]
+    fn chunks_missing_operands_fail() {
+        let span = Span::new(0, 1, 0, 0);
+
+        let chunk = Chunk {
+            code: vec![Instruction::Constant(0), Instruction::Binary { operator: 0, left: 0, right: 0 }],
+            constants: vec![Value::Number(Number::from(1))],
+            operators: vec![Token::new(TokenKind::Plus, "+", span)],
+            spans: vec![span],
+            ..Chunk::default()
+        };
+
+        let mut interpreter = Interpreter::with_rates(Rates::default());
+        let result = interpreter.run(&chunk, &mut Context::new());
+
+        assert_eq!(result.map_err(|error| error.kind()), Err(ErrorKind::MissingOperand));
+    }
+
+    #[test]
+    fn errors_in_functions_point_at_the_call() {
+        let lines = parse("g(a): a + true\ng(1)\nlongname(b): 2 * g(b)\n1 + longname(3)");
+        let results = run(&lines, &compile(&lines));
+
+        let Err(error) = &results[1] else { panic!("{:?}", results[1]) };
+        assert_eq!((error.span().start, error.span().end), (0, 4));
+        assert!(error.message().starts_with("in 'g': "), "{}", error.message());
+
+        let Err(error) = &results[3] else { panic!("{:?}", results[3]) };
+        assert_eq!((error.span().start, error.span().end), (4, 15));
+        assert!(error.message().starts_with("in 'longname': ") && !error.message().contains("in 'g'"), "{}", error.message());
+    }
+
+    #[test]
+    fn functions_only_see_their_own_parameters() {
+        let lines = parse("x: 10\ng(): a\nf(a): g()\nf(1)\nh(x): x + true\nh(1)\nx\nk(x): x * 2\nk(3) + x");
+        let results = run(&lines, &compile(&lines));
+
+        let shown: Vec<_> = results.iter().map(show).collect();
+        assert_eq!(shown, ["10", "", "", "in 'f': unknown name 'a'", "", "in 'h': expected a number", "10", "", "16"]);
+    }
+
+    #[test]
+    fn calls_with_the_wrong_number_of_arguments() {
+        let lines = parse("f(a, b): a + b\nf(1)\nsqrt(1, 2)\nmax()");
+        let results = run(&lines, &compile(&lines));
+
+        let shown: Vec<_> = results.iter().map(show).collect();
+        assert_eq!(shown, ["", "'f' expects 2 arguments, got 1", "'sqrt' expects 1 argument, got 2", "'max' expects at least 1 argument, got 0"]);
+    }
+
+    #[test]
+    fn dates_out_of_range() {
+        for line in ["today + 1e15 days", "today + 1e20 s", "today - 1e20 s", "today + 1e18 years", "2026-01-31 + 1e12 months"] {
+            let lines = parse(line);
+
+            match &run(&lines, &compile(&lines))[0] {
+                Err(error) => assert_eq!(error.message(), "the date is out of range", "{line}"),
+                result => panic!("{line}: {result:?}"),
+            }
+        }
+
+        let lines = parse("2026-01-31 + 1 month\n2026-01-31 + 3 m");
+        let results = run(&lines, &compile(&lines));
+
+        assert_eq!(results[0], Ok(Some(Value::Date(Date::from_ymd(2026, 2, 28).unwrap()))));
+        assert_eq!(results[1].as_ref().map_err(Error::kind), Err(ErrorKind::DimensionMismatch));
+    }
+
+    #[test]
+    fn time_units_in_the_plural() {
+        let lines = parse("2027-03-01 - 2027-01-01 in days\n1 day\n3 weeks in days\n-1 week\n12 km / 2 day\n0.5 year");
+        let results = run(&lines, &compile(&lines));
+
+        let shown: Vec<_> = results.iter().map(show).collect();
+        assert_eq!(shown, ["59 days", "1 day", "21 days", "-1 week", "6 km/day", "0.5 years"]);
+    }
+
+    #[test]
+    fn times_wrap_around_huge_durations() {
+        let lines = parse("9:30 + 1e20 s\n9:30 - 1e20 s\n23:00 + 2 h");
+        let results = run(&lines, &compile(&lines));
+
+        // 1e20 seconds is 35200 seconds more than a whole number of days.
+        assert_eq!(results.iter().map(show).collect::<Vec<_>>(), ["19:16:40", "23:43:20", "01:00"]);
+    }
+
+    #[test]
+    fn unit_exponents_out_of_range() {
+        for line in ["(2 m)^256", "(1 m)^1e5", "(1 m^100) * (1 m^100)", "1 m^100 / (1 m^-100)"] {
+            let lines = parse(line);
+
+            match &run(&lines, &compile(&lines))[0] {
+                Err(error) => assert_eq!(error.message(), "the exponent is too large", "{line}"),
+                result => panic!("{line}: {result:?}"),
+            }
+        }
+
+        let lines = parse("(1 m)^127 in km^127\n(1 L)^120\n(1 m^100) / (1 m^100)");
+        let results = run(&lines, &compile(&lines));
+
+        for (result, unit) in results.iter().zip(["km^127", "L^120"]) {
+            match result {
+                Ok(Some(Value::Quantity(quantity))) => assert_eq!(quantity.unit().to_string(), unit),
+                result => panic!("{result:?}"),
+            }
+        }
+
+        assert_eq!(results[2], Ok(Some(Value::Number(Number::from(1)))));
+    }
+}
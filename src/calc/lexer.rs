@@ -1,15 +1,35 @@
 use std::collections::HashMap;
 
+use crate::calc::currency;
+use crate::calc::date::{Date, Time};
+use crate::calc::error::{Error, ErrorKind, Result, Span};
+use crate::calc::locale::Separators;
+use crate::calc::number::Number;
+
+/// Suffixes that scale a number by a power of ten, as in `5k` or `3bn`. There
+/// is no `K` or `B` since those are kelvin and bytes.
+const MAGNITUDES: &[(&str, i64)] = &[
+    ("k", 3),
+    ("M", 6),
+    ("bn", 9),
+];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     Identifier(String),
-    Number(f64),
+    Number(Number),
+    Date(Date),
+    Time(Time),
+    Currency(String),
     True,
     False,
 
     Percent,
+    Caret,
+    Bang,
     Dot,
     Colon,
+    Comma,
     OpenParen,
     CloseParen,
 
@@ -18,29 +38,61 @@ pub enum TokenKind {
     Times,
     Over,
     Equal,
+    NotEqual,
     Less,
     LessEqual,
     Greater,
     GreaterEqual,
     And,
     Or,
+    Xor,
+    Not,
     Mod,
+    In,
+    To,
+    As,
+    Of,
 
+    Comment,
     Whitespace,
     Newline,
     Eof,
     Invalid,
 }
 
+/// Where the comment in a line starts, as a byte offset. Nothing else uses
+/// `#` or `//`, so this is where the lexer would stop reading the line too.
+pub fn comment_start(line: &str) -> Option<usize> {
+    [line.find('#'), line.find("//")].into_iter().flatten().min()
+}
+
+/// Whether a line is a heading like `# Groceries`.
+pub fn is_heading(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+/// The error for a `TokenKind::Invalid` token.
+pub fn invalid_token(token: &Token) -> Error {
+    let lexeme = token.lexeme();
+
+    let message = match lexeme.chars().count() {
+        1 => format!("unexpected character '{lexeme}'"),
+        _ => format!("invalid literal '{lexeme}'"),
+    };
+
+    Error::new(ErrorKind::InvalidToken, message, token.span())
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     kind: TokenKind,
     lexeme: String,
+    span: Span,
 }
 
 impl Token {
-    pub fn new(kind: TokenKind, lexeme: &str) -> Self {
-        Self { kind, lexeme: lexeme.to_string() }
+    pub fn new(kind: TokenKind, lexeme: &str, span: Span) -> Self {
+        Self { kind, lexeme: lexeme.to_string(), span }
     }
 
     pub fn kind(&self) -> TokenKind {
@@ -50,6 +102,10 @@ impl Token {
     pub fn lexeme(&self) -> String {
         self.lexeme.clone()
     }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
 pub struct Lexer {
@@ -57,11 +113,19 @@ pub struct Lexer {
     tokens: Vec<Token>,
     index: usize,
     start: usize,
+    line: usize,
+    column: usize,
+    depth: usize,
+    separators: Separators,
     keywords: HashMap<String, TokenKind>,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Self {
+        Lexer::with_separators(input, Separators::default())
+    }
+
+    pub fn with_separators(input: String, separators: Separators) -> Self {
         let mut keywords = HashMap::new();
         keywords.insert("true".to_string(), TokenKind::True);
         keywords.insert("false".to_string(), TokenKind::False);
@@ -72,33 +136,61 @@ impl Lexer {
         keywords.insert("equals".to_string(), TokenKind::Equal);
         keywords.insert("and".to_string(), TokenKind::And);
         keywords.insert("or".to_string(), TokenKind::Or);
+        keywords.insert("xor".to_string(), TokenKind::Xor);
+        keywords.insert("not".to_string(), TokenKind::Not);
         keywords.insert("mod".to_string(), TokenKind::Mod);
+        keywords.insert("in".to_string(), TokenKind::In);
+        keywords.insert("to".to_string(), TokenKind::To);
+        keywords.insert("as".to_string(), TokenKind::As);
+        keywords.insert("of".to_string(), TokenKind::Of);
 
         Self {
             input,
             tokens: vec![],
             index: 0,
             start: 0,
+            line: 0,
+            column: 0,
+            depth: 0,
+            separators,
             keywords,
         }
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    /// Tokenizes the input, failing at the first character or literal that
+    /// isn't valid.
+    pub fn tokenize(&mut self) -> Result<Vec<Token>> {
+        let tokens = self.tokenize_partial();
+
+        match tokens.iter().find(|token| token.kind() == TokenKind::Invalid) {
+            Some(token) => Err(invalid_token(token)),
+            None => Ok(tokens),
+        }
+    }
+
+    /// Tokenizes all of the input, leaving a `TokenKind::Invalid` token in
+    /// place of each thing that isn't valid, for the parser to report.
+    pub fn tokenize_partial(&mut self) -> Vec<Token> {
         while !self.is_at_end() {
             self.start = self.index;
+            let (line, column) = (self.line, self.column);
 
             let kind = self.consume_token();
-            self.tokens.push(Token::new(kind, &self.input[self.start..self.index]));
+            let lexeme = &self.input[self.start..self.index];
+            let span = Span::new(self.start, self.index, line, column);
+
+            self.tokens.push(Token::new(kind, lexeme, span));
         }
 
-        self.tokens.push(Token::new(TokenKind::Eof, "\0"));
+        let span = Span::new(self.index, self.index, self.line, self.column);
+        self.tokens.push(Token::new(TokenKind::Eof, "\0", span));
 
         self.tokens()
     }
 
     fn tokens(&self) -> Vec<Token> {
         self.tokens.iter()
-            .filter(|t| t.kind() != TokenKind::Whitespace)
+            .filter(|t| !matches!(t.kind(), TokenKind::Whitespace | TokenKind::Comment))
             .map(|t| t.clone())
             .collect()
     }
@@ -107,15 +199,41 @@ impl Lexer {
         let c = self.consume();
 
         match c {
-            '(' => TokenKind::OpenParen,
-            ')' => TokenKind::CloseParen,
+            '(' => {
+                self.depth += 1;
+                TokenKind::OpenParen
+            },
+            ')' => {
+                self.depth = self.depth.saturating_sub(1);
+                TokenKind::CloseParen
+            },
             '%' => TokenKind::Percent,
+            '^' => TokenKind::Caret,
             '.' => TokenKind::Dot,
             ':' => TokenKind::Colon,
+            // `;` separates arguments where `,` is the decimal separator.
+            ',' | ';' => TokenKind::Comma,
             '+' => TokenKind::Plus,
             '-' => TokenKind::Minus,
-            '*' => TokenKind::Times,
-            '/' => TokenKind::Over,
+            '*' => {
+                if self.consume_if('*') {
+                    return TokenKind::Caret;
+                }
+                TokenKind::Times
+            },
+            '!' => {
+                if self.consume_if('=') {
+                    return TokenKind::NotEqual;
+                }
+                TokenKind::Bang
+            },
+            '/' => {
+                if self.peek() == '/' {
+                    return self.consume_comment();
+                }
+                TokenKind::Over
+            },
+            '#' => self.consume_comment(),
             '=' => TokenKind::Equal,
             '<' => {
                 if self.consume_if('=') {
@@ -132,29 +250,214 @@ impl Lexer {
             '\n' => TokenKind::Newline,
             c if c.is_whitespace() => TokenKind::Whitespace,
             c if c.is_digit(10) => self.consume_number(),
-            c if c.is_alphabetic() => self.consume_identifier(),
-            _ => TokenKind::Invalid,
+            c if c.is_alphabetic() || c == '°' => self.consume_identifier(),
+            c => match currency::lookup(&c.to_string()) {
+                Some(code) => TokenKind::Currency(code.to_string()),
+                None => TokenKind::Invalid,
+            },
+        }
+    }
+
+    /// Skips to the end of the line after a `#` or `//`.
+    fn consume_comment(&mut self) -> TokenKind {
+        while !self.is_at_end() && self.peek() != '\n' {
+            self.consume();
         }
+
+        TokenKind::Comment
     }
 
     fn consume_number(&mut self) -> TokenKind {
-        while self.peek().is_digit(10) {
+        // `0xFF`, `0b1010` and `0o755`
+        if &self.input[self.start..self.index] == "0" {
+            let radix = match self.peek() {
+                'x' | 'X' => 16,
+                'b' | 'B' => 2,
+                'o' | 'O' => 8,
+                _ => 0,
+            };
+
+            if radix != 0 && self.peek_ahead(1).is_digit(radix) {
+                self.consume();
+                self.consume_digits(radix);
+
+                let digits = self.input[self.start + 2..self.index].replace('_', "");
+
+                return match Number::from_radix(&digits, radix) {
+                    Some(value) => self.consume_magnitude(value),
+                    None => TokenKind::Invalid,
+                };
+            }
+        }
+
+        self.consume_digits(10);
+
+        // `2026-12-25`
+        if self.index - self.start == 4 && self.is_date_ahead() {
+            return self.consume_date();
+        }
+
+        // `17:45`, `9:30am` and `9pm`. An assignment always has a name before
+        // its colon, so one right between digits can only be a clock time.
+        if self.index - self.start <= 2 && (self.is_minutes_ahead() || self.meridiem_ahead(0).is_some()) {
+            return self.consume_time();
+        }
+
+        // `1,000,000`, but only outside of parentheses when the comma could
+        // just as well separate arguments.
+        let Separators { decimal, grouping } = self.separators;
+        let leading = &self.input[self.start..self.index];
+
+        if (self.depth == 0 || grouping != ',') && leading.len() <= 3 && !leading.contains('_') {
+            while self.peek() == grouping
+                && (1..=3).all(|offset| self.peek_ahead(offset).is_ascii_digit())
+                && !self.peek_ahead(4).is_ascii_digit()
+                && self.peek_ahead(4) != '_'
+            {
+                for _ in 0..4 {
+                    self.consume();
+                }
+            }
+        }
+
+        if self.peek() == decimal && self.peek_ahead(1).is_ascii_digit() {
             self.consume();
+            self.consume_digits(10);
         }
 
-        if self.peek() == '.' && self.peek_ahead(1).is_digit(10) {
+        // `1.5e-3` and `6.02E23`
+        if matches!(self.peek(), 'e' | 'E') {
+            let sign = matches!(self.peek_ahead(1), '+' | '-') as usize;
+
+            if self.peek_ahead(1 + sign).is_ascii_digit() {
+                for _ in 0..=sign {
+                    self.consume();
+                }
+
+                self.consume_digits(10);
+            }
+        }
+
+        let text: String = self.input[self.start..self.index].chars()
+            .filter(|c| *c != '_' && *c != grouping)
+            .map(|c| if c == decimal { '.' } else { c })
+            .collect();
+
+        match Number::parse(&text) {
+            Some(value) => self.consume_magnitude(value),
+            None => TokenKind::Invalid,
+        }
+    }
+
+    fn is_date_ahead(&self) -> bool {
+        let pattern = "-dd-dd";
+
+        pattern.chars().enumerate().all(|(offset, expected)| match expected {
+            'd' => self.peek_ahead(offset).is_ascii_digit(),
+            _ => self.peek_ahead(offset) == expected,
+        }) && !self.peek_ahead(pattern.len()).is_ascii_digit()
+    }
+
+    fn consume_date(&mut self) -> TokenKind {
+        for _ in 0..6 {
             self.consume();
+        }
+
+        let text = &self.input[self.start..self.index];
+        let date = match (text[..4].parse(), text[5..7].parse(), text[8..].parse()) {
+            (Ok(year), Ok(month), Ok(day)) => Date::from_ymd(year, month, day),
+            _ => None,
+        };
+
+        match date {
+            Some(date) => TokenKind::Date(date),
+            None => TokenKind::Invalid,
+        }
+    }
+
+    /// Whether a `:30` of a clock time comes next.
+    fn is_minutes_ahead(&self) -> bool {
+        self.peek() == ':'
+            && self.peek_ahead(1).is_ascii_digit()
+            && self.peek_ahead(2).is_ascii_digit()
+            && !self.peek_ahead(3).is_ascii_digit()
+    }
+
+    /// Whether the word `offset` characters ahead is `am` (`false`) or `pm`
+    /// (`true`).
+    fn meridiem_ahead(&self, offset: usize) -> Option<bool> {
+        let word: String = self.input[self.index..].chars()
+            .skip(offset)
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+
+        match word.to_lowercase().as_str() {
+            "am" => Some(false),
+            "pm" => Some(true),
+            _ => None,
+        }
+    }
+
+    fn consume_time(&mut self) -> TokenKind {
+        let mut parts: Vec<u32> = vec![self.input[self.start..self.index].parse().unwrap_or_default()];
 
-            while self.peek().is_digit(10) {
+        // Minutes, and then maybe seconds.
+        while parts.len() < 3 && self.is_minutes_ahead() {
+            for _ in 0..3 {
                 self.consume();
             }
+
+            parts.push(self.input[self.index - 2..self.index].parse().unwrap_or_default());
         }
 
-        if let Ok(value) = self.input[self.start..self.index].parse() {
-            return TokenKind::Number(value);
+        let (hour, minute, second) = (parts[0], parts.get(1).copied().unwrap_or(0), parts.get(2).copied().unwrap_or(0));
+
+        // `9:30 pm` can have a space before the suffix, `9 pm` can't since
+        // that reads just as well as picometers.
+        let space = usize::from(parts.len() > 1 && self.peek() == ' ');
+
+        let time = match self.meridiem_ahead(space) {
+            Some(pm) => {
+                for _ in 0..space + 2 {
+                    self.consume();
+                }
+
+                Time::from_twelve_hour(hour, minute, second, pm)
+            }
+            None => Time::from_hms(hour, minute, second),
+        };
+
+        match time {
+            Some(time) => TokenKind::Time(time),
+            None => TokenKind::Invalid,
         }
+    }
 
-        TokenKind::Invalid
+    /// Takes digits in the given radix, allowing single underscores between
+    /// them as in `1_000_000`.
+    fn consume_digits(&mut self, radix: u32) {
+        while self.peek().is_digit(radix) || (self.peek() == '_' && self.peek_ahead(1).is_digit(radix)) {
+            self.consume();
+        }
+    }
+
+    /// Applies a suffix like the `k` in `5k`, as long as it isn't the start of
+    /// a longer word like the `km` in `5km`.
+    fn consume_magnitude(&mut self, value: Number) -> TokenKind {
+        let suffix: String = self.input[self.index..].chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+
+        match MAGNITUDES.iter().find(|(name, _)| *name == suffix) {
+            Some((name, places)) => {
+                for _ in 0..name.chars().count() {
+                    self.consume();
+                }
+
+                TokenKind::Number(value.shift(*places))
+            }
+            None => TokenKind::Number(value),
+        }
     }
 
     fn consume_identifier(&mut self) -> TokenKind {
@@ -168,12 +471,24 @@ impl Lexer {
             return kind.clone();
         }
 
+        if let Some(code) = currency::lookup(&text) {
+            return TokenKind::Currency(code.to_string());
+        }
+
         TokenKind::Identifier(text)
     }
 
     fn consume(&mut self) -> char {
         let c = self.peek();
-        self.index += 1;
+        self.index += c.len_utf8();
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+
         c
     }
 
@@ -182,25 +497,21 @@ impl Lexer {
             return false;
         }
 
-        if self.input.as_bytes()[self.index] as char != expected_character {
+        if self.peek() != expected_character {
             return false;
         }
 
-        self.index += 1;
+        self.consume();
 
         true
     }
 
-    fn peek(&mut self) -> char {
+    fn peek(&self) -> char {
         self.peek_ahead(0)
     }
 
-    fn peek_ahead(&mut self, offset: usize) -> char {
-        if self.index + offset >= self.input.len() {
-            return '\0';
-        }
-
-        self.input.as_bytes()[self.index + offset] as char
+    fn peek_ahead(&self, offset: usize) -> char {
+        self.input[self.index..].chars().nth(offset).unwrap_or('\0')
     }
 
     fn is_at_end(&self) -> bool {
@@ -0,0 +1,111 @@
+use std::ffi::CStr;
+
+/// The characters a sheet uses to write numbers, like the `,` and `.` in
+/// `1,234.5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Separators {
+    pub decimal: char,
+    /// Groups thousands in results and is skipped in input.
+    pub grouping: char,
+}
+
+impl Default for Separators {
+    /// The separators of the C locale, which is also what the rates file is
+    /// written in.
+    fn default() -> Self {
+        Self { decimal: '.', grouping: ',' }
+    }
+}
+
+impl Separators {
+    /// Reads the separators of the current `LC_NUMERIC` locale, as set up by
+    /// `setlocale` in `main`.
+    pub fn from_locale() -> Separators {
+        // SAFETY: `localeconv` returns a pointer to static storage that stays
+        // valid until the next call, and both fields are C strings.
+        let (decimal, grouping) = unsafe {
+            let conv = libc::localeconv();
+
+            if conv.is_null() {
+                return Separators::default();
+            }
+
+            (first_char((*conv).decimal_point), first_char((*conv).thousands_sep))
+        };
+
+        let decimal = decimal.unwrap_or('.');
+
+        // Locales like C don't group at all, so fall back to whatever doesn't
+        // clash with the decimal separator.
+        let grouping = grouping
+            .filter(|grouping| *grouping != decimal)
+            .unwrap_or(if decimal == ',' { '.' } else { ',' });
+
+        Separators { decimal, grouping }
+    }
+
+    /// Uses the separators asked for by a `decimal comma` or `decimal point`
+    /// line anywhere in the sheet, or the locale's when there's none.
+    pub fn for_sheet(text: &str) -> Separators {
+        text.lines()
+            .find_map(Separators::from_directive)
+            .unwrap_or_else(Separators::from_locale)
+    }
+
+    pub fn is_directive(line: &str) -> bool {
+        Separators::from_directive(line).is_some()
+    }
+
+    fn from_directive(line: &str) -> Option<Separators> {
+        match line.trim() {
+            "decimal comma" => Some(Separators { decimal: ',', grouping: '.' }),
+            "decimal point" => Some(Separators { decimal: '.', grouping: ',' }),
+            _ => None,
+        }
+    }
+
+    /// Rewrites the number at the start of a formatted result, like the
+    /// `1234.5` in `1234.5 km`, with these separators.
+    pub fn format(&self, text: &str) -> String {
+        let sign = usize::from(text.starts_with('-'));
+        let end = text[sign..]
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .map_or(text.len(), |index| index + sign);
+
+        let (number, rest) = text.split_at(end);
+        let (whole, fraction) = match number[sign..].split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (&number[sign..], None),
+        };
+
+        let mut result = number[..sign].to_string();
+
+        for (index, digit) in whole.chars().enumerate() {
+            if index > 0 && (whole.len() - index) % 3 == 0 {
+                result.push(self.grouping);
+            }
+
+            result.push(digit);
+        }
+
+        if let Some(fraction) = fraction {
+            result.push(self.decimal);
+            result.push_str(fraction);
+        }
+
+        result.push_str(rest);
+
+        result
+    }
+}
+
+/// # Safety
+///
+/// `pointer` has to be null or point to a valid C string.
+unsafe fn first_char(pointer: *const libc::c_char) -> Option<char> {
+    if pointer.is_null() {
+        return None;
+    }
+
+    CStr::from_ptr(pointer).to_str().ok()?.chars().next()
+}
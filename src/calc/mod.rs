@@ -1,3 +1,14 @@
 pub mod lexer;
 pub mod parser;
 pub mod interpreter;
+pub mod error;
+pub mod units;
+pub mod currency;
+pub mod number;
+pub mod functions;
+pub mod locale;
+pub mod date;
+pub mod zone;
+pub mod references;
+pub mod value;
+pub mod compiler;
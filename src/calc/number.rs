@@ -0,0 +1,470 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
+/// Decimal places shown when a number is formatted without a precision.
+const DEFAULT_PRECISION: usize = 10;
+
+/// Largest whole exponent worked out exactly, anything bigger would take
+/// forever to print anyway.
+const MAX_EXACT_POWER: u32 = 100_000;
+
+/// Largest power, in bits, worked out exactly. A small exponent can still
+/// make a huge number out of a big one, as in `(2^100000)^1000`.
+const MAX_EXACT_BITS: u64 = 1_000_000;
+
+/// Largest `n` that `n!` is worked out for.
+const MAX_FACTORIAL: u32 = 10_000;
+
+/// A number that stays exact as long as it can.
+///
+/// Whole numbers are big integers and everything else that can be written as
+/// a fraction, including decimal literals, is a big rational, so `1/3 * 3` is
+/// exactly `1` and `0.1 + 0.2` is exactly `0.3`. Only operations without an
+/// exact answer, like `2^0.5`, fall back to floating point, and anything that
+/// touches a float becomes a float too.
+#[derive(Debug, Clone)]
+pub enum Number {
+    Integer(BigInt),
+    Rational(BigRational),
+    Float(f64),
+}
+
+/// How an exact fraction is shown, as in `0.375 as fraction`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Notation {
+    /// `11/8`
+    Fraction,
+    /// `1 3/8`
+    Mixed,
+}
+
+impl Number {
+    /// Parses a decimal literal like `42`, `0.25` or `1.5e3`.
+    pub fn parse(text: &str) -> Option<Number> {
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(index) => (&text[..index], text[index + 1..].parse::<i32>().ok()?),
+            None => (text, 0),
+        };
+
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+
+        let digits: BigInt = format!("{whole}{fraction}").parse().ok()?;
+        let places = exponent.checked_sub(i32::try_from(fraction.len()).ok()?)?;
+
+        // `1e99999999` would be a hundred million digits long, so it's only
+        // as exact as a float, which makes it too large for one.
+        if places.unsigned_abs() > MAX_EXACT_POWER {
+            return Number::float(text.parse().ok()?);
+        }
+
+        Some(Number::Integer(digits).shift(places as i64))
+    }
+
+    /// Parses the digits of a hexadecimal, octal or binary literal.
+    pub fn from_radix(digits: &str, radix: u32) -> Option<Number> {
+        BigInt::parse_bytes(digits.as_bytes(), radix).map(Number::Integer)
+    }
+
+    /// Takes a floating point constant, like a unit factor, at its shortest
+    /// decimal representation, so `0.3048` is exactly `0.3048`.
+    pub fn from_f64(value: f64) -> Option<Number> {
+        if !value.is_finite() {
+            return None;
+        }
+
+        Number::parse(&value.to_string())
+    }
+
+    /// Wraps the inexact result of an operation, like a square root.
+    pub fn float(value: f64) -> Option<Number> {
+        if !value.is_finite() {
+            return None;
+        }
+
+        Some(Number::Float(value))
+    }
+
+    fn from_ratio(ratio: BigRational) -> Number {
+        if ratio.is_integer() {
+            return Number::Integer(ratio.to_integer());
+        }
+
+        Number::Rational(ratio)
+    }
+
+    fn ratio(&self) -> Option<BigRational> {
+        match self {
+            Number::Integer(integer) => Some(BigRational::from_integer(integer.clone())),
+            Number::Rational(ratio) => Some(ratio.clone()),
+            Number::Float(_) => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Integer(integer) => integer.to_f64(),
+            Number::Rational(ratio) => ratio.to_f64(),
+            Number::Float(float) => Some(*float),
+        }.unwrap_or(f64::NAN)
+    }
+
+    pub fn to_i32(&self) -> Option<i32> {
+        match self {
+            Number::Integer(integer) => integer.to_i32(),
+            Number::Float(float) if float.fract() == 0.0 => float.to_i32(),
+            _ => None,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Integer(integer) => integer.is_zero(),
+            Number::Rational(ratio) => ratio.is_zero(),
+            Number::Float(float) => *float == 0.0,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self {
+            Number::Integer(_) => true,
+            Number::Rational(_) => false,
+            Number::Float(float) => float.fract() == 0.0,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Number::Float(_))
+    }
+
+    /// Divides, returning `None` when `other` is zero.
+    pub fn checked_div(&self, other: &Number) -> Option<Number> {
+        if other.is_zero() {
+            return None;
+        }
+
+        match (self.ratio(), other.ratio()) {
+            (Some(x), Some(y)) => Some(Number::from_ratio(x / y)),
+            _ => Number::float(self.to_f64() / other.to_f64()),
+        }
+    }
+
+    /// Takes the remainder, with the sign of `self`, returning `None` when
+    /// `other` is zero.
+    pub fn checked_rem(&self, other: &Number) -> Option<Number> {
+        if other.is_zero() {
+            return None;
+        }
+
+        match (self, other) {
+            (Number::Integer(x), Number::Integer(y)) => Some(Number::Integer(x % y)),
+            _ => match (self.ratio(), other.ratio()) {
+                (Some(x), Some(y)) => Some(Number::from_ratio(x % y)),
+                _ => Number::float(self.to_f64() % other.to_f64()),
+            },
+        }
+    }
+
+    /// Raises to a whole power, returning `None` for negative powers of zero
+    /// and powers too large for a float.
+    pub fn powi(&self, power: i32) -> Option<Number> {
+        let bits = match self {
+            Number::Integer(integer) => integer.bits(),
+            Number::Rational(ratio) => ratio.numer().bits().max(ratio.denom().bits()),
+            Number::Float(_) => 0,
+        };
+
+        if bits.saturating_mul(u64::from(power.unsigned_abs())) > MAX_EXACT_BITS {
+            return Number::float(self.to_f64().powi(power));
+        }
+
+        let result = match self {
+            Number::Integer(integer) => Number::Integer(integer.pow(power.unsigned_abs())),
+            Number::Rational(ratio) => {
+                let numer = ratio.numer().pow(power.unsigned_abs());
+                let denom = ratio.denom().pow(power.unsigned_abs());
+
+                Number::Rational(BigRational::new(numer, denom))
+            }
+            Number::Float(float) => return Number::float(float.powi(power)),
+        };
+
+        if power < 0 {
+            return Number::from(1).checked_div(&result);
+        }
+
+        Some(result)
+    }
+
+    /// Raises to any power, exactly for whole powers of exact numbers and in
+    /// floating point otherwise. Returns `None` when there's no real answer.
+    pub fn pow(&self, power: &Number) -> Option<Number> {
+        if let (true, Number::Integer(exponent)) = (self.is_exact(), power) {
+            if let Some(exponent) = exponent.to_i32().filter(|exponent| exponent.unsigned_abs() <= MAX_EXACT_POWER) {
+                return self.powi(exponent);
+            }
+        }
+
+        Number::float(self.to_f64().powf(power.to_f64()))
+    }
+
+    /// `n!` for whole, non-negative `n`.
+    pub fn factorial(&self) -> Option<Number> {
+        let n = self.to_i32().and_then(|n| u32::try_from(n).ok())?;
+
+        if n > MAX_FACTORIAL {
+            return None;
+        }
+
+        Some(Number::Integer((1..=n).map(BigInt::from).product()))
+    }
+
+    /// The number as a big integer, when it's whole.
+    fn integer(&self) -> Option<BigInt> {
+        match self {
+            Number::Integer(integer) => Some(integer.clone()),
+            Number::Rational(_) => None,
+            Number::Float(float) if float.fract() == 0.0 => BigInt::from_f64(*float),
+            Number::Float(_) => None,
+        }
+    }
+
+    pub fn abs(&self) -> Number {
+        match self {
+            Number::Integer(integer) => Number::Integer(integer.abs()),
+            Number::Rational(ratio) => Number::Rational(ratio.abs()),
+            Number::Float(float) => Number::Float(float.abs()),
+        }
+    }
+
+    /// The square root, which stays exact for perfect squares like `16` or
+    /// `9/4`. Returns `None` for negative numbers.
+    pub fn sqrt(&self) -> Option<Number> {
+        let exact_root = |n: &BigInt| Some(n.sqrt()).filter(|root| root * root == *n);
+
+        match self {
+            Number::Integer(integer) if !integer.is_negative() => {
+                if let Some(root) = exact_root(integer) {
+                    return Some(Number::Integer(root));
+                }
+            }
+            Number::Rational(ratio) if !ratio.is_negative() => {
+                if let (Some(numer), Some(denom)) = (exact_root(ratio.numer()), exact_root(ratio.denom())) {
+                    return Some(Number::Rational(BigRational::new(numer, denom)));
+                }
+            }
+            _ => {}
+        }
+
+        Number::float(self.to_f64().sqrt())
+    }
+
+    /// Rounds half away from zero to `places` decimal places, which may be
+    /// negative to round to tens, hundreds and so on.
+    pub fn round(&self, places: i32) -> Option<Number> {
+        self.rounded(places, BigRational::round, f64::round)
+    }
+
+    pub fn floor(&self, places: i32) -> Option<Number> {
+        self.rounded(places, BigRational::floor, f64::floor)
+    }
+
+    pub fn ceil(&self, places: i32) -> Option<Number> {
+        self.rounded(places, BigRational::ceil, f64::ceil)
+    }
+
+    fn rounded(&self, places: i32, exact: fn(&BigRational) -> BigRational, float: fn(f64) -> f64) -> Option<Number> {
+        let scale = Number::from(10).powi(places)?;
+
+        match (self.ratio(), scale.ratio()) {
+            (Some(ratio), Some(scale)) => Some(Number::from_ratio(exact(&(ratio * &scale)) / scale)),
+            _ => Number::float(float(self.to_f64() * scale.to_f64()) / scale.to_f64()),
+        }
+    }
+
+    /// Greatest common divisor of two whole numbers.
+    pub fn gcd(&self, other: &Number) -> Option<Number> {
+        Some(Number::Integer(self.integer()?.gcd(&other.integer()?)))
+    }
+
+    /// Least common multiple of two whole numbers.
+    pub fn lcm(&self, other: &Number) -> Option<Number> {
+        Some(Number::Integer(self.integer()?.lcm(&other.integer()?)))
+    }
+
+    /// Multiplies by `10^places`, which is exact for decimals.
+    pub fn shift(&self, places: i64) -> Number {
+        let Ok(power) = i32::try_from(places) else {
+            return Number::Float(self.to_f64() * 10f64.powf(places as f64));
+        };
+
+        let scale = Number::from(10).powi(power).unwrap_or_default();
+
+        self.clone() * scale
+    }
+
+    /// Formats with exactly `places` decimal places, like `12.50`.
+    pub fn fixed(&self, places: usize) -> String {
+        match self.ratio() {
+            Some(ratio) => decimal(&ratio, places, false),
+            None => format!("{:.*}", places, self.to_f64()),
+        }
+    }
+
+    /// Formats exact numbers as a fraction like `3/8` or a mixed number like
+    /// `1 3/8`. Floats have no exact fraction and are shown as decimals.
+    pub fn format_fraction(&self, notation: Notation, places: usize) -> String {
+        let ratio = match self {
+            Number::Rational(ratio) => ratio,
+            _ => return format!("{:.*}", places, self),
+        };
+
+        let (numer, denom) = (ratio.numer(), ratio.denom());
+
+        match notation {
+            Notation::Fraction => format!("{numer}/{denom}"),
+            Notation::Mixed => {
+                let whole = ratio.trunc().to_integer();
+
+                if whole.is_zero() {
+                    return format!("{numer}/{denom}");
+                }
+
+                format!("{whole} {}/{denom}", (numer % denom).abs())
+            }
+        }
+    }
+}
+
+/// Writes `value` rounded half away from zero to `places` decimal places,
+/// dropping trailing zeros when `trim` is set.
+fn decimal(value: &BigRational, places: usize, trim: bool) -> String {
+    let scale = BigInt::from(10).pow(places as u32);
+    let scaled = (value * BigRational::from_integer(scale)).round().to_integer();
+
+    let mut digits = scaled.abs().to_string();
+
+    if places > 0 {
+        if digits.len() <= places {
+            digits = format!("{}{digits}", "0".repeat(places + 1 - digits.len()));
+        }
+
+        digits.insert(digits.len() - places, '.');
+
+        if trim {
+            digits = digits.trim_end_matches('0').trim_end_matches('.').to_string();
+        }
+    }
+
+    if scaled.is_negative() {
+        return format!("-{digits}");
+    }
+
+    digits
+}
+
+impl Default for Number {
+    fn default() -> Self {
+        Number::Integer(BigInt::zero())
+    }
+}
+
+impl From<i64> for Number {
+    fn from(value: i64) -> Self {
+        Number::Integer(BigInt::from(value))
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        match (self.ratio(), other.ratio()) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+}
+
+impl Add for Number {
+    type Output = Number;
+
+    fn add(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Integer(x), Number::Integer(y)) => Number::Integer(x + y),
+            (x, y) => match (x.ratio(), y.ratio()) {
+                (Some(a), Some(b)) => Number::from_ratio(a + b),
+                _ => Number::Float(x.to_f64() + y.to_f64()),
+            },
+        }
+    }
+}
+
+impl Sub for Number {
+    type Output = Number;
+
+    fn sub(self, other: Number) -> Number {
+        self + -other
+    }
+}
+
+impl Mul for Number {
+    type Output = Number;
+
+    fn mul(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Integer(x), Number::Integer(y)) => Number::Integer(x * y),
+            (x, y) => match (x.ratio(), y.ratio()) {
+                (Some(a), Some(b)) => Number::from_ratio(a * b),
+                _ => Number::Float(x.to_f64() * y.to_f64()),
+            },
+        }
+    }
+}
+
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Integer(integer) => Number::Integer(-integer),
+            Number::Rational(ratio) => Number::Rational(-ratio),
+            Number::Float(float) => Number::Float(-float),
+        }
+    }
+}
+
+/// Prints integers with all their digits and everything else without an
+/// exponent or trailing zeros. A precision, as in `{:.10}`, is the most
+/// decimal places to show.
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let places = f.precision().unwrap_or(DEFAULT_PRECISION);
+
+        match self {
+            Number::Integer(integer) => write!(f, "{integer}"),
+            Number::Rational(ratio) => write!(f, "{}", decimal(ratio, places, true)),
+            Number::Float(float) => {
+                let text = format!("{float:.places$}");
+
+                match text.contains('.') {
+                    true => write!(f, "{}", text.trim_end_matches('0').trim_end_matches('.')),
+                    false => write!(f, "{text}"),
+                }
+            }
+        }
+    }
+}
@@ -1,181 +1,645 @@
-use crate::calc::lexer::{Token, TokenKind};
+//! Operators from the loosest to the tightest binding:
+//!
+//! | Operators                          | Associativity |
+//! |------------------------------------|---------------|
+//! | `in`, `to`, `as`                   | left          |
+//! | `or`                               | left          |
+//! | `xor`                              | left          |
+//! | `and`                              | left          |
+//! | `not` (prefix)                     | right         |
+//! | `=`, `equals`, `!=`, `not equals`  | left          |
+//! | `<`, `<=`, `>`, `>=`               | left          |
+//! | `+`, `-`, `plus`, `minus`          | left          |
+//! | `*`, `/`, `times`, `over`, `mod`   | left          |
+//! | `of`                               | left          |
+//! | `-`, `+` (prefix)                  | right         |
+//! | `^`, `**`                          | right         |
+//! | `!`, `%` (postfix)                 | left          |
+//!
+//! So `-2^2` is `-4`, `2^3^2` is `2^9` and `2^3!` is `2^6`.
+//!
+//! The grammar of a sheet, where `word` is an identifier and a `name` is one
+//! or more words:
+//!
+//! ```text
+//! sheet      = { statement } ;
+//! statement  = ( aggregate | assignment | function | expression ) [ newline ] ;
+//! aggregate  = "sum" | "total" | "subtotal" | "average" | "avg" | "mean"
+//!            | "count" | "min" | "max" | "grand" "total" ;
+//! assignment = name ":" statement ;
+//! function   = word "(" [ word { "," word } ] ")" ":" expression ;
+//! name       = word { word } ;
+//!
+//! expression = or { ( "in" | "to" | "as" ) target } ;
+//! target     = "%" [ "of" or ] | "fraction" | "mixed" | zone | unit ;
+//! or         = xor { "or" xor } ;
+//! xor        = and { "xor" and } ;
+//! and        = not { "and" not } ;
+//! not        = "not" not | equality ;
+//! equality   = comparison { ( "=" | "!=" | "not" "equals" ) comparison } ;
+//! comparison = term { ( "<" | "<=" | ">" | ">=" ) term } ;
+//! term       = factor { ( "+" | "-" ) factor } ;
+//! factor     = of { ( "*" | "/" | "mod" ) of } ;
+//! of         = unary { "of" unary } ;
+//! unary      = ( "-" | "+" ) unary | power ;
+//! power      = postfix [ "^" unary ] ;
+//! postfix    = primary { "%" | "!" } ;
+//! primary    = "true" | "false"
+//!            | number [ unit { number unit } ]
+//!            | currency number [ unit-tail ]
+//!            | ( date [ time ] | time ) [ zone ]
+//!            | word "(" [ expression { "," expression } ] ")"
+//!            | month number [ [ "," ] year ]
+//!            | unit ( "until" | "since" ) term
+//!            | "weekday" "of" unary
+//!            | "%" "change" [ "from" ] or "to" or
+//!            | "(" expression ")"
+//!            | name ;
+//! unit       = unit-power unit-tail ;
+//! unit-tail  = { ( "*" | "/" ) unit-power } ;
+//! unit-power = word [ "^" [ "-" ] number ] ;
+//! zone       = word { "/" word } | word word ;
+//! ```
+//!
+//! A statement is told apart by looking ahead without consuming anything:
+//! words followed by `:` start an assignment, a word followed by a parameter
+//! list and `:` starts a function, and anything else is an expression, so
+//! `x + 1` and `area(3, 4)` keep their leading name. `1 USD = 0.92 EUR` is an
+//! equality between two amounts of money, which defines an exchange rate.
+//! Words that name nothing are labels and are skipped before an operand or
+//! after a value, as are lines of nothing but labels. Only a built-in or
+//! defined function is called, so `tip (15 + 3)` is a label and a group,
+//! while `tip(15)` is a call to an unknown function.
+
+use std::collections::HashSet;
+
+use crate::calc::date::{self, Date, Time};
+use crate::calc::error::{Error, ErrorKind, Result, Span};
+use crate::calc::functions;
+use crate::calc::lexer::{self, Token, TokenKind};
+use crate::calc::number::{Notation, Number};
+use crate::calc::references;
+use crate::calc::units::{Quantity, Unit};
+use crate::calc::zone::Zone;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Atom {
     Boolean(bool),
-    Number(f64),
+    Number(Number),
+    Quantity(Quantity),
+    /// A length of time in seconds, like `1h 30m`.
+    Duration(Number),
+    Date(Date),
+    Time(Time),
     Name(String),
 }
 
 impl Atom {
-    pub fn number(&self) -> Option<f64> {
+    pub fn number(&self) -> Option<&Number> {
         match self {
-            Atom::Number(number) => Some(*number),
+            Atom::Number(number) => Some(number),
             _ => None
         }
     }
 }
 
+/// What a line of just `sum` or `average` works out from the lines above it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Average,
+    Count,
+    Min,
+    Max,
+    /// The sum of every line of the sheet rather than just the block.
+    GrandTotal,
+}
+
+impl Aggregate {
+    fn from_name(name: &str) -> Option<Aggregate> {
+        match name {
+            "sum" | "total" | "subtotal" => Some(Aggregate::Sum),
+            "average" | "avg" | "mean" => Some(Aggregate::Average),
+            "count" => Some(Aggregate::Count),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            "grand total" => Some(Aggregate::GrandTotal),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     None,
-    Literal(Atom),
+    Literal(Atom, Span),
     Unary(Box<Expression>, Token),
     Binary(Box<Expression>, Box<Expression>, Token),
     Logical(Box<Expression>, Box<Expression>, Token),
     Group(Box<Expression>),
     Variable(String, Box<Expression>),
+    Function(String, Vec<String>, Box<Expression>),
+    Call(String, Vec<Expression>, Span),
+    Conversion(Box<Expression>, Unit, Span),
+    Rate(Quantity, Quantity, Span),
+    Percent(Box<Expression>, Token),
+    AsPercent(Box<Expression>, Option<Box<Expression>>, Span),
+    AsFraction(Box<Expression>, Notation, Span),
+    PercentChange(Box<Expression>, Box<Expression>, Span),
+    /// A date without a year, like `Dec 25`, which is the next time it comes
+    /// around.
+    MonthDay(u32, u32, Span),
+    Until(Unit, Box<Expression>, Span),
+    Since(Unit, Box<Expression>, Span),
+    Weekday(Box<Expression>, Span),
+    /// A time on the clocks of a zone, like `14:00 CET`.
+    InZone(Box<Expression>, Zone, Span),
+    /// A conversion to the clocks of a zone, like `3pm in Tokyo`.
+    ToZone(Box<Expression>, Zone, Span),
+    /// An aggregate and the words it was written as, which a variable of
+    /// the same name takes precedence over.
+    Aggregate(Aggregate, String, Span),
+    /// Something that didn't parse, kept in place so the rest of the tree
+    /// still can. Evaluating it gives its error.
+    Invalid(Error),
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::None => Span::default(),
+            Expression::Literal(_, span) => *span,
+            Expression::Unary(right, operator) => operator.span().to(right.span()),
+            Expression::Binary(left, right, _) => left.span().to(right.span()),
+            Expression::Logical(left, right, _) => left.span().to(right.span()),
+            Expression::Group(expression) => expression.span(),
+            Expression::Variable(_, value) => value.span(),
+            Expression::Function(_, _, body) => body.span(),
+            Expression::Call(_, _, span) => *span,
+            Expression::Conversion(expression, _, span) => expression.span().to(*span),
+            Expression::Rate(_, _, span) => *span,
+            Expression::Percent(value, operator) => value.span().to(operator.span()),
+            Expression::AsPercent(value, _, span) => value.span().to(*span),
+            Expression::AsFraction(value, _, span) => value.span().to(*span),
+            Expression::PercentChange(_, _, span) => *span,
+            Expression::MonthDay(_, _, span) => *span,
+            Expression::Until(_, date, span) => span.to(date.span()),
+            Expression::Since(_, date, span) => span.to(date.span()),
+            Expression::Weekday(date, span) => span.to(date.span()),
+            Expression::InZone(value, _, span) => value.span().to(*span),
+            Expression::ToZone(value, _, span) => value.span().to(*span),
+            Expression::Aggregate(_, _, span) => *span,
+            Expression::Invalid(error) => error.span(),
+        }
+    }
 }
 
 pub struct Parser {
     index: usize,
     tokens: Vec<Token>,
     expressions: Vec<Expression>,
+    errors: Vec<Error>,
+    /// Variables and functions defined so far, which aren't labels.
+    names: HashSet<String>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
+        Parser::with_names(tokens, HashSet::new())
+    }
+
+    /// A parser that knows which variables and functions the sheet has
+    /// defined, so it can tell them apart from labels.
+    pub fn with_names(tokens: Vec<Token>, names: HashSet<String>) -> Self {
         Self {
             index: 0,
             tokens,
             expressions: vec![],
+            errors: vec![],
+            names,
+        }
+    }
+
+    /// Parses every statement, failing with the first error.
+    pub fn parse(&mut self) -> Result<Vec<Expression>> {
+        let (expressions, errors) = self.parse_partial();
+
+        match errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(expressions),
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Expression> {
+    /// Parses every statement, carrying on past errors. A statement that
+    /// doesn't parse is skipped up to the end of its line, and a broken
+    /// group or argument up to its `)` or `,`, leaving `Expression::Invalid`
+    /// in its place.
+    pub fn parse_partial(&mut self) -> (Vec<Expression>, Vec<Error>) {
         while !self.is_at_end() {
-            let expr = self.parse_statement();
-            self.expressions.push(expr);
+            let expr = match self.parse_statement() {
+                Ok(expr) => expr,
+                Err(error) => {
+                    let invalid = self.invalid(error);
+
+                    while !self.is_at_end() && !self.r#match(vec![TokenKind::Newline]) {
+                        self.skip();
+                    }
+
+                    invalid
+                }
+            };
+
+            if expr != Expression::None {
+                self.expressions.push(expr);
+            }
         }
 
-        self.expressions.clone()
+        (self.expressions.clone(), self.errors.clone())
     }
 
-    fn parse_statement(&mut self) -> Expression {
-        if self.r#match(vec![TokenKind::Identifier(self.peek().lexeme())]) {
-            return self.parse_identifier();
+    fn parse_statement(&mut self) -> Result<Expression> {
+        if let Some(aggregate) = self.parse_aggregate() {
+            return Ok(aggregate);
         }
 
-        self.parse_expression()
-    }
+        // A line of nothing but labels, like a note, has no value.
+        let start = self.index;
+        self.skip_labels();
 
-    fn parse_identifier(&mut self) -> Expression {
-        let identifier = self.previous();
+        if self.is_at_end() || self.r#match(vec![TokenKind::Newline]) {
+            return Ok(Expression::None);
+        }
 
-        if self.r#match(vec![TokenKind::Colon]) {
-            let value = self.parse_statement();
+        self.index = start;
 
-            if self.check(TokenKind::Newline) {
+        // `rate: 40` and `hourly rate: 40`
+        let words = self.words_at(0);
+
+        if !words.is_empty() && self.kind_at(words.len()) == Some(TokenKind::Colon) {
+            for _ in 0..=words.len() {
                 self.consume();
             }
 
-            return Expression::Variable(identifier.lexeme(), Box::new(value));
+            return self.parse_assignment(words.join(" "));
+        }
+
+        // `area(w, h): w * h`
+        if words.len() == 1 && self.is_function_definition(1) {
+            return self.parse_function();
+        }
+
+        let expression = self.parse_expression()?;
+
+        Ok(self.parse_rate(expression))
+    }
+
+    /// A line with nothing but `total`, `avg` or `grand total` on it.
+    fn parse_aggregate(&mut self) -> Option<Expression> {
+        let words = self.words_at(0);
+
+        if !matches!(self.kind_at(words.len()), Some(TokenKind::Newline | TokenKind::Eof)) {
+            return None;
+        }
+
+        let name = words.join(" ");
+        let aggregate = Aggregate::from_name(&name)?;
+
+        let start = self.peek().span();
+        let mut span = start;
+
+        for _ in 0..words.len() {
+            span = start.to(self.consume().span());
+        }
+
+        if self.check(TokenKind::Newline) {
+            self.consume();
+        }
+
+        Some(Expression::Aggregate(aggregate, name, span))
+    }
+
+    /// Turns `1 USD = 0.92 EUR` into an exchange rate definition rather than
+    /// a comparison.
+    fn parse_rate(&self, expression: Expression) -> Expression {
+        if let Expression::Binary(left, right, operator) = &expression {
+            if let (Expression::Literal(Atom::Quantity(from), _), Expression::Literal(Atom::Quantity(to), _)) = (left.as_ref(), right.as_ref()) {
+                if operator.kind() == TokenKind::Equal
+                    && from.unit().is_currency()
+                    && to.unit().is_currency()
+                    && from.unit() != to.unit()
+                {
+                    return Expression::Rate(from.clone(), to.clone(), expression.span());
+                }
+            }
+        }
+
+        expression
+    }
+
+    /// A definition like `area(w, h): w * h`, whose parameters are names
+    /// rather than labels inside the body.
+    fn parse_function(&mut self) -> Result<Expression> {
+        let name = self.consume().lexeme();
+        let parameters = self.parse_parameters()?;
+        self.consume_with(TokenKind::Colon, "expected ':' after parameters")?;
+
+        // The function can call itself, and the lines after it can too.
+        self.names.insert(name.clone());
+
+        let names = self.names.clone();
+        self.names.extend(parameters.iter().cloned());
+        let body = self.parse_expression();
+        self.names = names;
+        let body = body?;
+
+        if self.check(TokenKind::Newline) {
+            self.consume();
+        }
+
+        Ok(Expression::Function(name, parameters, Box::new(body)))
+    }
+
+    /// The value after the `:` of `name: value`.
+    fn parse_assignment(&mut self, name: String) -> Result<Expression> {
+        let value = self.parse_statement()?;
+
+        if self.check(TokenKind::Newline) {
+            self.consume();
+        }
+
+        Ok(Expression::Variable(name, Box::new(value)))
+    }
+
+    /// Looks past `(a, b)` for the colon that tells a definition apart from a
+    /// call like `area(3, 4)`.
+    fn is_function_definition(&self, offset: usize) -> bool {
+        if self.kind_at(offset) != Some(TokenKind::OpenParen) {
+            return false;
+        }
+
+        let mut offset = offset + 1;
+
+        loop {
+            match self.kind_at(offset) {
+                Some(TokenKind::Identifier(_) | TokenKind::Comma) => offset += 1,
+                Some(TokenKind::CloseParen) => return self.kind_at(offset + 1) == Some(TokenKind::Colon),
+                _ => return false,
+            }
+        }
+    }
+
+    fn parse_parameters(&mut self) -> Result<Vec<String>> {
+        self.consume_with(TokenKind::OpenParen, "expected '(' before parameters")?;
+        let mut parameters = vec![];
+
+        if !self.check(TokenKind::CloseParen) {
+            loop {
+                let parameter = self.peek();
+
+                match parameter.kind() {
+                    TokenKind::Identifier(name) if !parameters.contains(&name) => {
+                        self.consume();
+                        parameters.push(name);
+                    }
+                    TokenKind::Identifier(name) => {
+                        return Err(Error::new(ErrorKind::UnexpectedToken, format!("duplicate parameter '{name}'"), parameter.span()));
+                    }
+                    _ => return Err(Error::new(ErrorKind::UnexpectedToken, "expected a parameter name", parameter.span())),
+                }
+
+                if !self.r#match(vec![TokenKind::Comma]) {
+                    break;
+                }
+            }
         }
 
-        self.parse_statement()
+        self.consume_with(TokenKind::CloseParen, "expected ')' after parameters")?;
+
+        Ok(parameters)
     }
 
-    fn parse_expression(&mut self) -> Expression {
-        self.parse_or()
+    fn parse_expression(&mut self) -> Result<Expression> {
+        self.parse_conversion()
     }
 
-    fn parse_or(&mut self) -> Expression {
-        let mut expression = self.parse_and();
+    fn parse_conversion(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_or()?;
+
+        while self.r#match(vec![TokenKind::In, TokenKind::To, TokenKind::As]) {
+            // `0.25 as %` and `30 as % of 120`
+            if self.r#match(vec![TokenKind::Percent]) {
+                let mut span = self.previous().span();
+                let mut whole = None;
+
+                if self.r#match(vec![TokenKind::Of]) {
+                    let expression = self.parse_or()?;
+                    span = span.to(expression.span());
+                    whole = Some(Box::new(expression));
+                }
+
+                expression = Expression::AsPercent(Box::new(expression), whole, span);
+                continue;
+            }
+
+            // `0.375 as fraction` and `11/8 as mixed`
+            if let Some(notation) = self.notation_at(0) {
+                let span = self.consume().span();
+                expression = Expression::AsFraction(Box::new(expression), notation, span);
+                continue;
+            }
+
+            // `3pm in Tokyo` and `now in America/New_York`
+            if self.unit_at(0).is_none() {
+                if let Some((zone, span)) = self.parse_zone() {
+                    expression = Expression::ToZone(Box::new(expression), zone, span);
+                    continue;
+                }
+            }
+
+            let (unit, span) = self.parse_unit()?;
+            expression = Expression::Conversion(Box::new(expression), unit, span);
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_or(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_xor()?;
 
         while self.r#match(vec![TokenKind::Or]) {
             let operator = self.previous();
-            let right = self.parse_and();
+            let right = self.parse_xor()?;
             expression = Expression::Logical(Box::new(expression), Box::new(right), operator);
         }
 
-        expression
+        Ok(expression)
+    }
+
+    /// Unlike `and` and `or`, `xor` needs both sides, so it's a plain binary
+    /// operator.
+    fn parse_xor(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_and()?;
+
+        while self.r#match(vec![TokenKind::Xor]) {
+            let operator = self.previous();
+            let right = self.parse_and()?;
+            expression = Expression::Binary(Box::new(expression), Box::new(right), operator);
+        }
+
+        Ok(expression)
     }
 
-    fn parse_and(&mut self) -> Expression {
-        let mut expression = self.parse_equality();
+    fn parse_and(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_not()?;
 
         while self.r#match(vec![TokenKind::And]) {
             let operator = self.previous();
-            let right = self.parse_equality();
+            let right = self.parse_not()?;
             expression = Expression::Logical(Box::new(expression), Box::new(right), operator);
         }
 
-        expression
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression> {
+        if self.check(TokenKind::Not) && self.kind_at(1) != Some(TokenKind::Equal) {
+            let operator = self.consume();
+            let right = self.parse_not()?;
+            return Ok(Expression::Unary(Box::new(right), operator));
+        }
+
+        self.parse_equality()
     }
 
-    fn parse_equality(&mut self) -> Expression {
-        let mut expression = self.parse_comparison();
+    fn parse_equality(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_comparison()?;
 
-        while self.r#match([TokenKind::Equal].to_vec()) {
-            let operator = self.previous();
-            let right = self.parse_comparison();
+        loop {
+            let operator = if self.r#match(vec![TokenKind::Equal, TokenKind::NotEqual]) {
+                self.previous()
+            } else if self.check(TokenKind::Not) && self.kind_at(1) == Some(TokenKind::Equal) {
+                // `not equals`
+                let not = self.consume();
+                let equals = self.consume();
+                Token::new(TokenKind::NotEqual, "not equals", not.span().to(equals.span()))
+            } else {
+                break;
+            };
+
+            let right = self.parse_comparison()?;
             expression = Expression::Binary(Box::new(expression), Box::new(right), operator);
         }
 
-        expression
+        Ok(expression)
     }
 
-    fn parse_comparison(&mut self) -> Expression {
-        let mut expression = self.parse_term();
+    fn parse_comparison(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_term()?;
 
         while self.r#match([TokenKind::Greater, TokenKind::GreaterEqual, TokenKind::Less, TokenKind::LessEqual].to_vec()) {
             let operator = self.previous();
-            let right = self.parse_term();
+            let right = self.parse_term()?;
             expression = Expression::Binary(Box::new(expression), Box::new(right), operator);
         }
 
-        expression
+        Ok(expression)
     }
 
-    fn parse_term(&mut self) -> Expression {
-        let mut expression = self.parse_factor();
+    fn parse_term(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_factor()?;
 
         while self.r#match([TokenKind::Minus, TokenKind::Plus].to_vec()) {
             let operator = self.previous();
-            let right = self.parse_factor();
+            let right = self.parse_factor()?;
             expression = Expression::Binary(Box::new(expression), Box::new(right), operator);
         }
 
-        expression
+        Ok(expression)
     }
 
-    fn parse_factor(&mut self) -> Expression {
-        let mut expression = self.parse_unary();
+    fn parse_factor(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_of()?;
 
         while self.r#match([TokenKind::Over, TokenKind::Times, TokenKind::Mod].to_vec()) {
             let operator = self.previous();
-            let right = self.parse_unary();
+            let right = self.parse_of()?;
             expression = Expression::Binary(Box::new(expression), Box::new(right), operator);
         }
 
-        expression
+        Ok(expression)
     }
 
-    fn parse_unary(&mut self) -> Expression {
-        if self.r#match(vec![TokenKind::Minus]) {
+    fn parse_of(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_unary()?;
+
+        while self.r#match(vec![TokenKind::Of]) {
             let operator = self.previous();
-            let right = self.parse_unary();
-            return Expression::Unary(Box::new(right), operator);
+            let right = self.parse_unary()?;
+            expression = Expression::Binary(Box::new(expression), Box::new(right), operator);
         }
 
-        self.parse_primary()
+        Ok(expression)
     }
 
-    fn parse_primary(&mut self) -> Expression {
+    fn parse_unary(&mut self) -> Result<Expression> {
+        if self.r#match(vec![TokenKind::Minus, TokenKind::Plus]) {
+            let operator = self.previous();
+            let right = self.parse_unary()?;
+            return Ok(Expression::Unary(Box::new(right), operator));
+        }
+
+        self.parse_power()
+    }
+
+    /// The exponent goes back through `parse_unary`, which makes `^` right
+    /// associative and allows `2^-1`.
+    fn parse_power(&mut self) -> Result<Expression> {
+        let expression = self.parse_postfix()?;
+
+        if self.r#match(vec![TokenKind::Caret]) {
+            let operator = self.previous();
+            let right = self.parse_unary()?;
+            return Ok(Expression::Binary(Box::new(expression), Box::new(right), operator));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_primary()?;
+
+        loop {
+            self.skip_labels();
+
+            if self.r#match(vec![TokenKind::Percent]) {
+                expression = Expression::Percent(Box::new(expression), self.previous());
+            } else if self.r#match(vec![TokenKind::Bang]) {
+                expression = Expression::Unary(Box::new(expression), self.previous());
+            } else {
+                break;
+            }
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression> {
         if self.r#match(vec![TokenKind::False]) {
+            let span = self.previous().span();
             if self.check(TokenKind::Newline) {
                 self.consume();
             }
-            return Expression::Literal(Atom::Boolean(false));
+            return Ok(Expression::Literal(Atom::Boolean(false), span));
         }
         if self.r#match(vec![TokenKind::True]) {
+            let span = self.previous().span();
             if self.check(TokenKind::Newline) {
                 self.consume();
             }
-            return Expression::Literal(Atom::Boolean(true));
+            return Ok(Expression::Literal(Atom::Boolean(true), span));
         }
 
-        let mut number_value = 0.0;
+        let mut number_value = Number::default();
         let mut string_value = "".to_string();
 
         match self.peek().kind() {
@@ -188,37 +652,454 @@ impl Parser {
             _ => {},
         }
 
+        if let TokenKind::Date(date) = self.peek().kind() {
+            let span = self.consume().span();
+
+            // `2026-12-25 17:45`
+            let (date, span) = match self.peek().kind() {
+                TokenKind::Time(time) => (date.at(&time), span.to(self.consume().span())),
+                _ => (date, span),
+            };
+
+            return Ok(self.parse_zone_suffix(Expression::Literal(Atom::Date(date), span)));
+        }
+
+        if let TokenKind::Time(time) = self.peek().kind() {
+            let span = self.consume().span();
+            return Ok(self.parse_zone_suffix(Expression::Literal(Atom::Time(time), span)));
+        }
+
+        // `hourly rate * 160`
+        if let Some(length) = self.name_length_at(0).filter(|length| *length > 1) {
+            let words = self.words_at(0)[..length].join(" ");
+
+            let start = self.peek().span();
+            let mut span = start;
+
+            for _ in 0..length {
+                span = start.to(self.consume().span());
+            }
+
+            return Ok(Expression::Literal(Atom::Name(words), span));
+        }
+
         if self.r#match(vec![TokenKind::Identifier(string_value.clone())]) {
+            let span = self.previous().span();
+
+            // `sqrt(2)`, but `tip (15 + 3)` is a label and a group. Without
+            // the space it's a call, so a typo like `sqr(16)` isn't a label.
+            if self.check(TokenKind::OpenParen) {
+                if self.is_function(&string_value) {
+                    return self.parse_call(string_value, span);
+                }
+
+                if self.peek().span().start == span.end {
+                    return Err(Error::new(ErrorKind::UnknownName, format!("unknown function '{string_value}'"), span));
+                }
+            }
+
+            if let Some(expression) = self.parse_date(&string_value, span)? {
+                return Ok(expression);
+            }
+
+            // `Lunch with team 45`, but a typo like `pric * 2` is still an
+            // unknown name.
+            if self.is_label(&string_value) && self.is_operand_at(0) {
+                return self.parse_primary();
+            }
+
             if self.check(TokenKind::Newline) {
                 self.consume();
             }
-            return Expression::Literal(Atom::Name(string_value.clone()));
+            return Ok(Expression::Literal(Atom::Name(string_value.clone()), span));
         }
 
-        if self.r#match(vec![TokenKind::Number(number_value)]) {
+        if self.r#match(vec![TokenKind::Number(number_value.clone())]) {
+            let span = self.previous().span();
+
+            if self.unit_at(0).is_some() {
+                let (unit, unit_span) = self.parse_unit()?;
+                let quantity = Quantity::new(number_value, unit);
+
+                if quantity.unit().is_time() && self.is_duration_part_at(0) {
+                    return self.parse_duration(quantity, span.to(unit_span));
+                }
+
+                return Ok(Expression::Literal(Atom::Quantity(quantity), span.to(unit_span)));
+            }
+
             if self.check(TokenKind::Newline) {
                 self.consume();
             }
-            return Expression::Literal(Atom::Number(number_value));
+            return Ok(Expression::Literal(Atom::Number(number_value), span));
+        }
+
+        // `% change from 40 to 50`
+        if self.check(TokenKind::Percent) && self.kind_at(1) == Some(TokenKind::Identifier("change".to_string())) {
+            let start = self.consume().span();
+            self.consume();
+            self.r#match(vec![TokenKind::Identifier("from".to_string())]);
+
+            let from = self.parse_or()?;
+            self.consume_with(TokenKind::To, "expected 'to' after the starting value")?;
+            let to = self.parse_or()?;
+
+            let span = start.to(to.span());
+            return Ok(Expression::PercentChange(Box::new(from), Box::new(to), span));
+        }
+
+        // Amounts with the currency symbol in front, like `$40` or `€12.50/h`.
+        if let (TokenKind::Currency(code), Some(TokenKind::Number(amount))) = (self.peek().kind(), self.kind_at(1)) {
+            let symbol = self.consume();
+            let number = self.consume();
+
+            let span = symbol.span().to(number.span());
+            let (unit, span) = self.parse_unit_tail(Unit::from_currency(&code), span)?;
+
+            return Ok(Expression::Literal(Atom::Quantity(Quantity::new(amount, unit)), span));
         }
 
         if self.r#match([TokenKind::OpenParen].to_vec()) {
-            let expression = self.parse_expression();
+            let expression = self.parse_expression()
+                .unwrap_or_else(|error| self.recover(error, &[]));
 
-            self.consume_with(TokenKind::CloseParen, "expected ')' after expression");
+            // A missing `)` is taken as read, so `(1 + 2` is still `3`. It
+            // isn't reported again after an error inside the group.
+            if let Err(error) = self.consume_with(TokenKind::CloseParen, "expected ')' after expression") {
+                if !matches!(expression, Expression::Invalid(_)) {
+                    self.errors.push(error);
+                }
+            }
 
             if self.check(TokenKind::Newline) {
                 self.consume();
             }
 
-            return Expression::Group(Box::new(expression));
+            return Ok(Expression::Group(Box::new(expression)));
         }
 
-        if self.check(TokenKind::Newline) {
+        let token = self.peek();
+
+        if token.kind() == TokenKind::Eof || token.kind() == TokenKind::Newline {
+            return Err(Error::new(ErrorKind::MissingOperand, "expected a value", token.span()));
+        }
+
+        if token.kind() == TokenKind::Invalid {
+            self.consume();
+            return Err(lexer::invalid_token(&token));
+        }
+
+        Err(Error::new(ErrorKind::UnexpectedToken, format!("unexpected '{}'", token.lexeme()), token.span()))
+    }
+
+    /// Date expressions that start with a word: `Dec 25`, `March 1, 2027`,
+    /// `days until March 1`, `weeks since 2026-01-05` and `weekday of today`.
+    fn parse_date(&mut self, word: &str, span: Span) -> Result<Option<Expression>> {
+        if let (Some(month), Some(TokenKind::Number(day))) = (date::month(word), self.kind_at(0)) {
+            let day_token = self.consume();
+
+            let Some(day) = day.to_i32().and_then(|day| u32::try_from(day).ok()) else {
+                return Err(Error::new(ErrorKind::UnexpectedToken, "expected a day of the month", day_token.span()));
+            };
+
+            let mut span = span.to(day_token.span());
+            let comma = usize::from(self.check(TokenKind::Comma));
+
+            // An optional year, which has to have four digits so `Dec 25, 3`
+            // can still be two arguments.
+            if let Some(TokenKind::Number(year)) = self.kind_at(comma) {
+                if let Some(year) = year.to_i32().filter(|year| (1000..=9999).contains(year)) {
+                    for _ in 0..=comma {
+                        span = span.to(self.consume().span());
+                    }
+
+                    return match Date::from_ymd(year as i64, month, day) {
+                        Some(date) => Ok(Some(Expression::Literal(Atom::Date(date), span))),
+                        None => Err(Error::new(ErrorKind::OutOfDomain, "no such date", span)),
+                    };
+                }
+            }
+
+            return Ok(Some(Expression::MonthDay(month, day, span)));
+        }
+
+        if let Some(TokenKind::Identifier(direction)) = self.kind_at(0) {
+            if let ("until" | "since", Some(unit)) = (direction.as_str(), Unit::lookup(word)) {
+                self.consume();
+                let date = self.parse_term()?;
+
+                return match direction.as_str() {
+                    "until" => Ok(Some(Expression::Until(unit, Box::new(date), span))),
+                    _ => Ok(Some(Expression::Since(unit, Box::new(date), span))),
+                };
+            }
+        }
+
+        if word == "weekday" && self.r#match(vec![TokenKind::Of]) {
+            let date = self.parse_unary()?;
+            return Ok(Some(Expression::Weekday(Box::new(date), span)));
+        }
+
+        Ok(None)
+    }
+
+    /// Adds up the rest of a duration written in parts, like the `30m` in
+    /// `1h 30m`, where `m` means minutes rather than meters.
+    fn parse_duration(&mut self, first: Quantity, mut span: Span) -> Result<Expression> {
+        let too_large = |span| Error::new(ErrorKind::OutOfDomain, "the duration is too large", span);
+        let mut seconds = first.base_value().ok_or_else(|| too_large(span))?;
+
+        while let (Some(TokenKind::Number(amount)), Some(unit)) = (self.kind_at(0), self.duration_unit_at(1)) {
+            self.consume();
+            span = span.to(self.consume().span());
+
+            seconds = seconds + Quantity::new(amount, unit).base_value().ok_or_else(|| too_large(span))?;
+        }
+
+        Ok(Expression::Literal(Atom::Duration(seconds), span))
+    }
+
+    fn is_duration_part_at(&self, offset: usize) -> bool {
+        matches!(self.kind_at(offset), Some(TokenKind::Number(_))) && self.duration_unit_at(offset + 1).is_some()
+    }
+
+    fn duration_unit_at(&self, offset: usize) -> Option<Unit> {
+        match self.kind_at(offset)? {
+            TokenKind::Identifier(name) if name == "m" => Unit::lookup("min"),
+            TokenKind::Identifier(name) => Unit::lookup(&name).filter(Unit::is_time),
+            _ => None,
+        }
+    }
+
+    /// The zone a date or time is given in, like the `CET` in `14:00 CET`.
+    fn parse_zone_suffix(&mut self, expression: Expression) -> Expression {
+        match self.parse_zone() {
+            Some((zone, span)) => Expression::InZone(Box::new(expression), zone, span),
+            None => expression,
+        }
+    }
+
+    /// A time zone like `Tokyo`, `New York`, `PST` or `America/New_York`.
+    /// Nothing is consumed when the words don't name a zone.
+    fn parse_zone(&mut self) -> Option<(Zone, Span)> {
+        let word = |offset| match self.kind_at(offset) {
+            Some(TokenKind::Identifier(word)) => Some(word),
+            _ => None,
+        };
+
+        let mut name = word(0)?;
+        let mut length = 1;
+
+        while self.kind_at(length) == Some(TokenKind::Over) {
+            let Some(part) = word(length + 1) else {
+                break;
+            };
+
+            name = format!("{name}/{part}");
+            length += 2;
+        }
+
+        let (zone, length) = match word(length) {
+            Some(next) if length == 1 => match Zone::lookup(&format!("{name} {next}")) {
+                Some(zone) => (zone, 2),
+                None => (Zone::lookup(&name)?, 1),
+            },
+            _ => (Zone::lookup(&name)?, length),
+        };
+
+        let start = self.peek().span();
+        let mut span = start;
+
+        for _ in 0..length {
+            span = start.to(self.consume().span());
+        }
+
+        Some((zone, span))
+    }
+
+    fn parse_call(&mut self, name: String, span: Span) -> Result<Expression> {
+        self.consume();
+        let mut arguments = vec![];
+
+        if !self.check(TokenKind::CloseParen) {
+            loop {
+                let argument = self.parse_expression()
+                    .unwrap_or_else(|error| self.recover(error, &[TokenKind::Comma]));
+                arguments.push(argument);
+
+                if !self.r#match(vec![TokenKind::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        let span = match self.consume_with(TokenKind::CloseParen, "expected ')' after arguments") {
+            Ok(close) => span.to(close.span()),
+            Err(error) => {
+                if !matches!(arguments.last(), Some(Expression::Invalid(_))) {
+                    self.errors.push(error);
+                }
+
+                span.to(self.previous().span())
+            }
+        };
+
+        Ok(Expression::Call(name, arguments, span))
+    }
+
+    /// Parses a unit like `km`, `km/h` or `kg*m/s^2`. Operators are only
+    /// taken as part of the unit when another unit follows them, so `5 m / 2`
+    /// still divides.
+    fn parse_unit(&mut self) -> Result<(Unit, Span)> {
+        let (unit, span) = self.parse_unit_power()?;
+
+        self.parse_unit_tail(unit, span)
+    }
+
+    fn parse_unit_tail(&mut self, mut unit: Unit, mut span: Span) -> Result<(Unit, Span)> {
+        while (self.check(TokenKind::Times) || self.check(TokenKind::Over)) && self.unit_at(1).is_some() {
+            let operator = self.consume();
+            let (right, right_span) = self.parse_unit_power()?;
+
+            let product = match operator.kind() {
+                TokenKind::Times => unit.multiply(&right),
+                _ => unit.divide(&right),
+            };
+
+            span = span.to(right_span);
+            unit = product.ok_or_else(|| Error::new(ErrorKind::OutOfDomain, "the exponent is too large", span))?;
+        }
+
+        Ok((unit, span))
+    }
+
+    fn parse_unit_power(&mut self) -> Result<(Unit, Span)> {
+        let token = self.peek();
+
+        let Some(mut unit) = self.unit_at(0) else {
+            return Err(Error::new(ErrorKind::UnknownUnit, format!("unknown unit '{}'", token.lexeme()), token.span()));
+        };
+
+        self.consume();
+        let mut span = token.span();
+
+        if self.r#match(vec![TokenKind::Caret]) {
+            let negative = self.r#match(vec![TokenKind::Minus]);
+            let exponent = self.peek();
+
+            match exponent.kind() {
+                TokenKind::Number(power) if power.to_i32().is_some() => {
+                    self.consume();
+
+                    let power = power.to_i32().unwrap_or_default();
+                    span = span.to(exponent.span());
+
+                    unit = unit.pow(if negative { -power } else { power })
+                        .ok_or_else(|| Error::new(ErrorKind::OutOfDomain, "the exponent is too large", span))?;
+                }
+                _ => return Err(Error::new(ErrorKind::UnexpectedToken, "expected a whole number exponent", exponent.span())),
+            }
+        }
+
+        Ok((unit, span))
+    }
+
+    /// Whether a word doesn't name anything, like the words in `Lunch with
+    /// team 45 + tip 15%`, so it's only there for the reader.
+    fn is_label(&self, word: &str) -> bool {
+        !self.names.contains(word)
+            && Unit::lookup(word).is_none()
+            && !date::KEYWORDS.contains(&word)
+            && !references::PREVIOUS.contains(&word)
+            && references::line_number(word).is_none()
+    }
+
+    /// Whether a name can be called, as a built-in function or one the sheet
+    /// defines.
+    fn is_function(&self, name: &str) -> bool {
+        self.names.contains(name) || functions::lookup(name).is_some()
+    }
+
+    /// The words in a row from `offset` on, like `hourly rate`.
+    fn words_at(&self, offset: usize) -> Vec<String> {
+        let mut words = vec![];
+
+        while let Some(TokenKind::Identifier(word)) = self.kind_at(offset + words.len()) {
+            words.push(word);
+        }
+
+        words
+    }
+
+    /// How many words at `offset` make up the longest defined name, so
+    /// `hourly rate` wins over `rate`.
+    fn name_length_at(&self, offset: usize) -> Option<usize> {
+        let words = self.words_at(offset);
+
+        (1..=words.len()).rev().find(|length| self.names.contains(&words[..*length].join(" ")))
+    }
+
+    /// Whether the token at `offset` can start an operand.
+    fn is_operand_at(&self, offset: usize) -> bool {
+        matches!(
+            self.kind_at(offset),
+            Some(
+                TokenKind::Number(_)
+                | TokenKind::Identifier(_)
+                | TokenKind::Date(_)
+                | TokenKind::Time(_)
+                | TokenKind::Currency(_)
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::OpenParen
+                | TokenKind::Percent
+                | TokenKind::Minus
+                | TokenKind::Plus
+                | TokenKind::Not
+            )
+        )
+    }
+
+    /// Skips labels, but not names that are being defined or called. Once
+    /// in a label, units are words too, as in `12 square meters`.
+    fn skip_labels(&mut self) {
+        let mut in_label = false;
+
+        while let Some(TokenKind::Identifier(word)) = self.kind_at(0) {
+            let is_named = matches!(self.kind_at(1), Some(TokenKind::Colon | TokenKind::OpenParen))
+                || self.name_length_at(0).is_some();
+            let is_word = self.is_label(&word) || (in_label && Unit::lookup(&word).is_some());
+
+            if is_named || !is_word {
+                break;
+            }
+
             self.consume();
+            in_label = true;
+        }
+    }
+
+    fn notation_at(&self, offset: usize) -> Option<Notation> {
+        match self.kind_at(offset)? {
+            TokenKind::Identifier(name) => match name.as_str() {
+                "fraction" | "fractions" => Some(Notation::Fraction),
+                "mixed" => Some(Notation::Mixed),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn unit_at(&self, offset: usize) -> Option<Unit> {
+        match self.kind_at(offset) {
+            Some(TokenKind::Identifier(name)) => Unit::lookup(&name),
+            Some(TokenKind::Currency(code)) => Some(Unit::from_currency(&code)),
+            _ => None,
         }
+    }
 
-        Expression::Literal(Atom::Name(self.previous().lexeme()))
+    fn kind_at(&self, offset: usize) -> Option<TokenKind> {
+        self.tokens.get(self.index + offset).map(|token| token.kind())
     }
 
     fn r#match(&mut self, kinds: Vec<TokenKind>) -> bool {
@@ -249,14 +1130,55 @@ impl Parser {
         self.previous()
     }
 
-    fn consume_with(&mut self, kind: TokenKind, message: &str) -> Token {
-        if self.check(kind) {
-            return self.consume();
+    /// Records an error inside a group or argument list and skips what's
+    /// left of it, so parsing can go on after it.
+    fn recover(&mut self, error: Error, stops: &[TokenKind]) -> Expression {
+        self.synchronize(stops);
+        self.invalid(error)
+    }
+
+    fn invalid(&mut self, error: Error) -> Expression {
+        self.errors.push(error.clone());
+        Expression::Invalid(error)
+    }
+
+    /// Skips ahead to the `)` that closes the current group, one of `stops`
+    /// outside any nested group, or the end of the line, without consuming
+    /// it.
+    fn synchronize(&mut self, stops: &[TokenKind]) {
+        let mut depth = 0;
+
+        loop {
+            let kind = self.peek().kind();
+
+            match kind {
+                TokenKind::Newline | TokenKind::Eof => return,
+                TokenKind::CloseParen if depth == 0 => return,
+                _ if depth == 0 && stops.contains(&kind) => return,
+                TokenKind::OpenParen => depth += 1,
+                TokenKind::CloseParen => depth -= 1,
+                _ => {}
+            }
+
+            self.skip();
+        }
+    }
+
+    /// Skips a token, reporting it if it isn't valid, as nothing else will.
+    fn skip(&mut self) {
+        let token = self.consume();
+
+        if token.kind() == TokenKind::Invalid {
+            self.errors.push(lexer::invalid_token(&token));
         }
+    }
 
-        println!("error at '{:?}': {}", self.peek().kind(), message);
+    fn consume_with(&mut self, kind: TokenKind, message: &str) -> Result<Token> {
+        if self.check(kind) {
+            return Ok(self.consume());
+        }
 
-        Token::new(TokenKind::Invalid, "")
+        Err(Error::new(ErrorKind::UnexpectedToken, message, self.peek().span()))
     }
 
 
@@ -266,7 +1188,7 @@ impl Parser {
 
     fn previous(&self) -> Token {
         if self.tokens.len() < 2 {
-            return Token::new(TokenKind::Invalid, "");
+            return Token::new(TokenKind::Invalid, "", Span::default());
         }
 
         self.tokens.get(self.index - 1).unwrap().clone()
@@ -277,3 +1199,183 @@ impl Parser {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::lexer::Lexer;
+
+    fn parse_with(names: &[&str], input: &str) -> Result<Vec<Expression>> {
+        let tokens = Lexer::new(input.to_string()).tokenize()?;
+        let names = names.iter().map(|name| name.to_string()).collect();
+
+        Parser::with_names(tokens, names).parse()
+    }
+
+    /// The statements of `input` written out with their structure, like
+    /// `(+ 1 (* 2 3))`.
+    fn parse(names: &[&str], input: &str) -> String {
+        let statements = parse_with(names, input).unwrap_or_else(|error| panic!("{input}: {error:?}"));
+
+        statements.iter().map(show).collect::<Vec<_>>().join("; ")
+    }
+
+    fn show(expression: &Expression) -> String {
+        match expression {
+            Expression::None => "none".to_string(),
+            Expression::Literal(Atom::Boolean(value), _) => value.to_string(),
+            Expression::Literal(Atom::Number(number), _) => number.to_string(),
+            Expression::Literal(Atom::Quantity(quantity), _) => format!("[{quantity}]"),
+            Expression::Literal(Atom::Name(name), _) => name.clone(),
+            Expression::Literal(atom, _) => format!("{atom:?}"),
+            Expression::Unary(right, operator) => format!("({} {})", operator.lexeme(), show(right)),
+            Expression::Binary(left, right, operator) | Expression::Logical(left, right, operator) => {
+                format!("({} {} {})", operator.lexeme(), show(left), show(right))
+            }
+            Expression::Group(expression) => show(expression),
+            Expression::Variable(name, value) => format!("(let {name} {})", show(value)),
+            Expression::Function(name, parameters, body) => format!("(fn {name} ({}) {})", parameters.join(" "), show(body)),
+            Expression::Call(name, arguments, _) => {
+                let arguments: Vec<_> = arguments.iter().map(show).collect();
+                format!("({name} {})", arguments.join(" "))
+            }
+            Expression::Conversion(value, unit, _) => format!("(in {} {unit})", show(value)),
+            Expression::Rate(from, to, _) => format!("(rate [{from}] [{to}])"),
+            Expression::Percent(value, _) => format!("(% {})", show(value)),
+            Expression::Aggregate(_, name, _) => format!("({name})"),
+            Expression::Invalid(_) => "invalid".to_string(),
+            expression => format!("{expression:?}"),
+        }
+    }
+
+    fn error(input: &str) -> String {
+        match parse_with(&[], input) {
+            Ok(statements) => panic!("{input} parsed as {statements:?}"),
+            Err(error) => error.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(parse(&[], "1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(parse(&[], "(1 + 2) * 3"), "(* (+ 1 2) 3)");
+        assert_eq!(parse(&[], "1 - 2 - 3"), "(- (- 1 2) 3)");
+        assert_eq!(parse(&[], "8 / 4 / 2"), "(/ (/ 8 4) 2)");
+        assert_eq!(parse(&[], "7 mod 4 * 2"), "(* (mod 7 4) 2)");
+        assert_eq!(parse(&[], "2 plus 3 times 4"), "(plus 2 (times 3 4))");
+    }
+
+    #[test]
+    fn powers_and_signs() {
+        assert_eq!(parse(&[], "-2^2"), "(- (^ 2 2))");
+        assert_eq!(parse(&[], "2^3^2"), "(^ 2 (^ 3 2))");
+        assert_eq!(parse(&[], "2^-1"), "(^ 2 (- 1))");
+        assert_eq!(parse(&[], "--1"), "(- (- 1))");
+        assert_eq!(parse(&[], "2 * -3"), "(* 2 (- 3))");
+    }
+
+    #[test]
+    fn postfix() {
+        assert_eq!(parse(&[], "2^3!"), "(^ 2 (! 3))");
+        assert_eq!(parse(&[], "3!!"), "(! (! 3))");
+        assert_eq!(parse(&[], "-3!"), "(- (! 3))");
+        assert_eq!(parse(&[], "20% of 50"), "(of (% 20) 50)");
+        assert_eq!(parse(&[], "2 * 20% of 50"), "(* 2 (of (% 20) 50))");
+    }
+
+    #[test]
+    fn comparison_and_logic() {
+        assert_eq!(parse(&[], "1 + 1 < 3"), "(< (+ 1 1) 3)");
+        assert_eq!(parse(&[], "1 < 2 = true"), "(= (< 1 2) true)");
+        assert_eq!(parse(&[], "1 not equals 2"), "(not equals 1 2)");
+        assert_eq!(parse(&[], "not 1 = 2"), "(not (= 1 2))");
+        assert_eq!(parse(&[], "true or false and false"), "(or true (and false false))");
+        assert_eq!(parse(&[], "true xor false or true"), "(or (xor true false) true)");
+        assert_eq!(parse(&[], "not not true"), "(not (not true))");
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(parse(&[], "1 + 2 km in m"), "(in (+ 1 [2 km]) m)");
+        assert_eq!(parse(&[], "1 km in m in cm"), "(in (in [1 km] m) cm)");
+        assert_eq!(parse(&[], "60 km/h to m/s"), "(in [60 km/h] m/s)");
+        assert_eq!(parse(&[], "5 m / 2"), "(/ [5 m] 2)");
+        assert_eq!(parse(&[], "1 m^127 in cm^127"), "(in [1 m^127] cm^127)");
+        assert_eq!(error("1 m^256 + 5"), "the exponent is too large");
+        assert_eq!(error("1 m^100 * m^100"), "the exponent is too large");
+        assert_eq!(error("1 m in km^256"), "the exponent is too large");
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(parse(&[], "x: 1 + 2"), "(let x (+ 1 2))");
+        assert_eq!(parse(&[], "hourly rate: 40"), "(let hourly rate 40)");
+        assert_eq!(parse(&[], "x: y: 2"), "(let x (let y 2))");
+        assert_eq!(parse(&[], "x: 1\ny: 2"), "(let x 1); (let y 2)");
+    }
+
+    #[test]
+    fn expressions_starting_with_a_name() {
+        assert_eq!(parse(&["x"], "x + 1"), "(+ x 1)");
+        assert_eq!(parse(&["x"], "x"), "x");
+        assert_eq!(parse(&["x"], "x^2 * 3"), "(* (^ x 2) 3)");
+        assert_eq!(parse(&["x", "y"], "x = y"), "(= x y)");
+        assert_eq!(parse(&["hourly rate"], "hourly rate * 160"), "(* hourly rate 160)");
+        assert_eq!(parse(&["x"], "x in m"), "(in x m)");
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(parse(&[], "area(w, h): w * h"), "(fn area (w h) (* w h))");
+        assert_eq!(parse(&[], "zero(): 0"), "(fn zero () 0)");
+        assert_eq!(parse(&["area"], "area(3, 4)"), "(area 3 4)");
+        assert_eq!(parse(&["area"], "area(3, 4) + 1"), "(+ (area 3 4) 1)");
+        assert_eq!(parse(&[], "sqrt(2 + 2) * 2"), "(* (sqrt (+ 2 2)) 2)");
+        assert_eq!(parse(&[], "f(x): x\nf(2)"), "(fn f (x) x); (f 2)");
+        assert_eq!(parse(&[], "fact(n): n * fact(n - 1)"), "(fn fact (n) (* n (fact (- n 1))))");
+        assert_eq!(parse(&[], "tip (15 + 3)"), "(+ 15 3)");
+    }
+
+    #[test]
+    fn sheets() {
+        assert_eq!(parse(&[], "1\n\n2"), "1; 2");
+        assert_eq!(parse(&[], "total"), "(total)");
+        assert_eq!(parse(&[], "grand total"), "(grand total)");
+        assert_eq!(parse(&[], "groceries"), "");
+        assert_eq!(parse(&[], "Lunch with team 45 + tip 15%"), "(+ 45 (% 15))");
+        assert_eq!(parse(&[], "1 USD = 0.92 EUR"), "(rate [1.00 USD] [0.92 EUR])");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("1 +"), "expected a value");
+        assert_eq!(error("(1 + 2"), "expected ')' after expression");
+        assert_eq!(error("f(x, x): x"), "duplicate parameter 'x'");
+        assert_eq!(error("1 in nothing"), "unknown unit 'nothing'");
+        assert_eq!(error("tip(15) * 2"), "unknown function 'tip'");
+        assert_eq!(error("Lunch sqr(16)"), "unknown function 'sqr'");
+    }
+
+    /// The statements that parsed and the messages of the errors.
+    fn recover(input: &str) -> (String, Vec<String>) {
+        let tokens = Lexer::new(input.to_string()).tokenize_partial();
+        let (statements, errors) = Parser::new(tokens).parse_partial();
+
+        let statements = statements.iter().map(show).collect::<Vec<_>>().join("; ");
+        (statements, errors.iter().map(|error| error.message().to_string()).collect())
+    }
+
+    #[test]
+    fn recovery() {
+        assert_eq!(recover("1 +\n2 * 3"), ("invalid; (* 2 3)".to_string(), vec!["expected a value".to_string()]));
+        assert_eq!(recover("1 + )\nx: 2"), ("invalid; (let x 2)".to_string(), vec!["unexpected ')'".to_string()]));
+        assert_eq!(recover("(1 + 2"), ("(+ 1 2)".to_string(), vec!["expected ')' after expression".to_string()]));
+        assert_eq!(recover("(1 +"), ("invalid".to_string(), vec!["expected a value".to_string()]));
+        assert_eq!(recover("(1 * ) + (2 / )"), ("(+ invalid invalid)".to_string(), vec!["unexpected ')'".to_string(), "unexpected ')'".to_string()]));
+        assert_eq!(recover("((1 +) * 2) + 3"), ("(+ (* invalid 2) 3)".to_string(), vec!["unexpected ')'".to_string()]));
+        assert_eq!(recover("max(1 +, 2, * 3)"), ("(max invalid 2 invalid)".to_string(), vec!["unexpected ','".to_string(), "unexpected '*'".to_string()]));
+        assert_eq!(recover("max(1, 2"), ("(max 1 2)".to_string(), vec!["expected ')' after arguments".to_string()]));
+        assert_eq!(recover("1 + @ + 2 & 3\n4"), ("invalid; 4".to_string(), vec!["unexpected character '@'".to_string(), "unexpected character '&'".to_string()]));
+        assert_eq!(recover("max(@, 2, & 4)"), ("(max invalid 2 invalid)".to_string(), vec!["unexpected character '@'".to_string(), "unexpected character '&'".to_string()]));
+    }
+}
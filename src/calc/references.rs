@@ -0,0 +1,65 @@
+use crate::calc::error::Span;
+use crate::calc::value::{Binding, Context, Value};
+
+/// Names that stand for the closest line above with a value.
+pub const PREVIOUS: &[&str] = &["prev", "ans"];
+
+/// The line a name like `line3` refers to, counting from 1.
+pub fn line_number(name: &str) -> Option<usize> {
+    let digits = name.strip_prefix("line")?;
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok().filter(|number| *number > 0)
+}
+
+/// Whether a name stands for the value of another line.
+pub fn is_reference(name: &str) -> bool {
+    PREVIOUS.contains(&name) || line_number(name).is_some()
+}
+
+/// Makes the value of a line, counting from 0, available to the lines
+/// after it as `lineN` and `prev`.
+pub fn record(context: &mut Context, index: usize, value: Value) {
+    let value = Binding::Value(value);
+
+    for name in PREVIOUS {
+        context.insert(name.to_string(), value.clone());
+    }
+
+    context.insert(format!("line{}", index + 1), value);
+}
+
+/// The edits that keep the `lineN` references in a line of text pointing at
+/// the same lines after others were inserted or removed. `moved` gives the
+/// new number of a line from its old one.
+pub fn renumber(text: &str, moved: impl Fn(usize) -> usize) -> Vec<(Span, String)> {
+    let mut edits = vec![];
+    let mut start = None;
+
+    // Words are only split here, like the lexer would, so that lines which
+    // don't evaluate still get their references fixed.
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        if c.is_alphanumeric() || c == '_' {
+            start.get_or_insert(index);
+            continue;
+        }
+
+        let Some(word_start) = start.take() else {
+            continue;
+        };
+
+        if let Some(number) = line_number(&text[word_start..index]) {
+            let new = moved(number);
+
+            if new != number {
+                let span = Span::new(word_start, index, 0, text[..word_start].chars().count());
+                edits.push((span, format!("line{new}")));
+            }
+        }
+    }
+
+    edits
+}
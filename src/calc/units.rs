@@ -0,0 +1,487 @@
+use std::fmt;
+
+use crate::calc::currency;
+use crate::calc::number::Number;
+
+/// Exponents of the base dimensions a unit is made of, in the order length,
+/// mass, time, electric current, temperature, amount of substance, data and
+/// money.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Dimension([i32; 8]);
+
+const NONE: Dimension = Dimension([0, 0, 0, 0, 0, 0, 0, 0]);
+const LENGTH: Dimension = Dimension([1, 0, 0, 0, 0, 0, 0, 0]);
+const AREA: Dimension = Dimension([2, 0, 0, 0, 0, 0, 0, 0]);
+const VOLUME: Dimension = Dimension([3, 0, 0, 0, 0, 0, 0, 0]);
+const MASS: Dimension = Dimension([0, 1, 0, 0, 0, 0, 0, 0]);
+const TIME: Dimension = Dimension([0, 0, 1, 0, 0, 0, 0, 0]);
+const FREQUENCY: Dimension = Dimension([0, 0, -1, 0, 0, 0, 0, 0]);
+const SPEED: Dimension = Dimension([1, 0, -1, 0, 0, 0, 0, 0]);
+const FORCE: Dimension = Dimension([1, 1, -2, 0, 0, 0, 0, 0]);
+const PRESSURE: Dimension = Dimension([-1, 1, -2, 0, 0, 0, 0, 0]);
+const ENERGY: Dimension = Dimension([2, 1, -2, 0, 0, 0, 0, 0]);
+const POWER: Dimension = Dimension([2, 1, -3, 0, 0, 0, 0, 0]);
+const CURRENT: Dimension = Dimension([0, 0, 0, 1, 0, 0, 0, 0]);
+const VOLTAGE: Dimension = Dimension([2, 1, -3, -1, 0, 0, 0, 0]);
+const TEMPERATURE: Dimension = Dimension([0, 0, 0, 0, 1, 0, 0, 0]);
+const AMOUNT: Dimension = Dimension([0, 0, 0, 0, 0, 1, 0, 0]);
+const DATA: Dimension = Dimension([0, 0, 0, 0, 0, 0, 1, 0]);
+const MONEY: Dimension = Dimension([0, 0, 0, 0, 0, 0, 0, 1]);
+
+impl Dimension {
+    fn combine(self, other: Dimension, power: i8) -> Dimension {
+        let mut exponents = self.0;
+
+        for (exponent, other) in exponents.iter_mut().zip(other.0) {
+            *exponent += other * i32::from(power);
+        }
+
+        Dimension(exponents)
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == NONE
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Prefixes {
+    None,
+    Metric,
+    Data,
+}
+
+/// A named unit, with its scale relative to the SI base units of its
+/// dimension. `offset` is only used by temperature scales.
+struct Definition {
+    symbols: &'static [&'static str],
+    names: &'static [&'static str],
+    factor: f64,
+    offset: f64,
+    dimension: Dimension,
+    prefixes: Prefixes,
+}
+
+const fn unit(symbols: &'static [&'static str], names: &'static [&'static str], factor: f64, dimension: Dimension, prefixes: Prefixes) -> Definition {
+    Definition { symbols, names, factor, offset: 0.0, dimension, prefixes }
+}
+
+const DEFINITIONS: &[Definition] = &[
+    // Length
+    unit(&["m"], &["meter", "meters", "metre", "metres"], 1.0, LENGTH, Prefixes::Metric),
+    unit(&["inch"], &["inches"], 0.0254, LENGTH, Prefixes::None),
+    unit(&["ft"], &["foot", "feet"], 0.3048, LENGTH, Prefixes::None),
+    unit(&["yd"], &["yard", "yards"], 0.9144, LENGTH, Prefixes::None),
+    unit(&["mi"], &["mile", "miles"], 1609.344, LENGTH, Prefixes::None),
+    unit(&["nmi"], &["nautical_mile", "nautical_miles"], 1852.0, LENGTH, Prefixes::None),
+
+    // Area and volume
+    unit(&["ha"], &["hectare", "hectares"], 1e4, AREA, Prefixes::None),
+    unit(&["acre"], &["acres"], 4046.8564224, AREA, Prefixes::None),
+    unit(&["L", "l"], &["liter", "liters", "litre", "litres"], 1e-3, VOLUME, Prefixes::Metric),
+    unit(&["gal"], &["gallon", "gallons"], 3.785411784e-3, VOLUME, Prefixes::None),
+    unit(&["qt"], &["quart", "quarts"], 9.46352946e-4, VOLUME, Prefixes::None),
+    unit(&["pt"], &["pint", "pints"], 4.73176473e-4, VOLUME, Prefixes::None),
+    unit(&["cup"], &["cups"], 2.365882365e-4, VOLUME, Prefixes::None),
+    unit(&["floz"], &["fluid_ounce", "fluid_ounces"], 2.95735295625e-5, VOLUME, Prefixes::None),
+    unit(&["tbsp"], &["tablespoon", "tablespoons"], 1.478676478125e-5, VOLUME, Prefixes::None),
+    unit(&["tsp"], &["teaspoon", "teaspoons"], 4.92892159375e-6, VOLUME, Prefixes::None),
+
+    // Mass
+    unit(&["g"], &["gram", "grams", "gramme", "grammes"], 1e-3, MASS, Prefixes::Metric),
+    unit(&["t"], &["tonne", "tonnes"], 1e3, MASS, Prefixes::Metric),
+    unit(&["lb", "lbs"], &["pound", "pounds"], 0.45359237, MASS, Prefixes::None),
+    unit(&["oz"], &["ounce", "ounces"], 0.028349523125, MASS, Prefixes::None),
+    unit(&["st"], &["stone", "stones"], 6.35029318, MASS, Prefixes::None),
+
+    // Time
+    unit(&["s", "sec"], &["second", "seconds"], 1.0, TIME, Prefixes::Metric),
+    unit(&["min"], &["minute", "minutes", "mins"], 60.0, TIME, Prefixes::None),
+    unit(&["h", "hr"], &["hour", "hours", "hrs"], 3600.0, TIME, Prefixes::None),
+    unit(&["day", "d"], &["days"], 86400.0, TIME, Prefixes::None),
+    unit(&["week"], &["weeks"], 604800.0, TIME, Prefixes::None),
+    unit(&["month"], &["months"], 2629800.0, TIME, Prefixes::None),
+    unit(&["year"], &["years"], 31557600.0, TIME, Prefixes::None),
+    unit(&["Hz"], &["hertz"], 1.0, FREQUENCY, Prefixes::Metric),
+
+    // Speed
+    unit(&["mph"], &[], 0.44704, SPEED, Prefixes::None),
+    unit(&["kph"], &[], 1.0 / 3.6, SPEED, Prefixes::None),
+    unit(&["kn"], &["knot", "knots"], 1852.0 / 3600.0, SPEED, Prefixes::None),
+
+    // Mechanics
+    unit(&["N"], &["newton", "newtons"], 1.0, FORCE, Prefixes::Metric),
+    unit(&["Pa"], &["pascal", "pascals"], 1.0, PRESSURE, Prefixes::Metric),
+    unit(&["bar"], &["bars"], 1e5, PRESSURE, Prefixes::Metric),
+    unit(&["atm"], &["atmosphere", "atmospheres"], 101325.0, PRESSURE, Prefixes::None),
+    unit(&["psi"], &[], 6894.757293168, PRESSURE, Prefixes::None),
+    unit(&["J"], &["joule", "joules"], 1.0, ENERGY, Prefixes::Metric),
+    unit(&["cal"], &["calorie", "calories"], 4.184, ENERGY, Prefixes::Metric),
+    unit(&["Wh"], &["watt_hour", "watt_hours"], 3600.0, ENERGY, Prefixes::Metric),
+    unit(&["eV"], &["electronvolt", "electronvolts"], 1.602176634e-19, ENERGY, Prefixes::Metric),
+    unit(&["BTU"], &["btu"], 1055.05585262, ENERGY, Prefixes::None),
+    unit(&["W"], &["watt", "watts"], 1.0, POWER, Prefixes::Metric),
+    unit(&["hp"], &["horsepower"], 745.69987158227, POWER, Prefixes::None),
+
+    // Electricity and chemistry
+    unit(&["A"], &["amp", "amps", "ampere", "amperes"], 1.0, CURRENT, Prefixes::Metric),
+    unit(&["V"], &["volt", "volts"], 1.0, VOLTAGE, Prefixes::Metric),
+    unit(&["mol"], &["mole", "moles"], 1.0, AMOUNT, Prefixes::Metric),
+
+    // Temperature
+    unit(&["K"], &["kelvin", "kelvins"], 1.0, TEMPERATURE, Prefixes::None),
+    Definition {
+        symbols: &["°C", "degC"],
+        names: &["celsius"],
+        factor: 1.0,
+        offset: 273.15,
+        dimension: TEMPERATURE,
+        prefixes: Prefixes::None,
+    },
+    Definition {
+        symbols: &["°F", "degF"],
+        names: &["fahrenheit"],
+        factor: 5.0 / 9.0,
+        offset: 459.67 * 5.0 / 9.0,
+        dimension: TEMPERATURE,
+        prefixes: Prefixes::None,
+    },
+
+    // Data
+    unit(&["bit"], &["bits"], 1.0, DATA, Prefixes::Data),
+    unit(&["B"], &["byte", "bytes"], 8.0, DATA, Prefixes::Data),
+];
+
+/// Symbol, long name and scale of each prefix.
+const METRIC_PREFIXES: &[(&str, &str, f64)] = &[
+    ("P", "peta", 1e15),
+    ("T", "tera", 1e12),
+    ("G", "giga", 1e9),
+    ("M", "mega", 1e6),
+    ("k", "kilo", 1e3),
+    ("h", "hecto", 1e2),
+    ("d", "deci", 1e-1),
+    ("c", "centi", 1e-2),
+    ("m", "milli", 1e-3),
+    ("µ", "micro", 1e-6),
+    ("u", "micro", 1e-6),
+    ("n", "nano", 1e-9),
+    ("p", "pico", 1e-12),
+];
+
+const DATA_PREFIXES: &[(&str, &str, f64)] = &[
+    ("Ki", "kibi", 1024.0),
+    ("Mi", "mebi", 1048576.0),
+    ("Gi", "gibi", 1073741824.0),
+    ("Ti", "tebi", 1099511627776.0),
+    ("P", "peta", 1e15),
+    ("T", "tera", 1e12),
+    ("G", "giga", 1e9),
+    ("M", "mega", 1e6),
+    ("k", "kilo", 1e3),
+    ("K", "kilo", 1e3),
+];
+
+/// Symbols that are words, with the plural they take after an amount other
+/// than one, like `75 days`.
+const PLURALS: &[(&str, &str)] = &[
+    ("day", "days"),
+    ("week", "weeks"),
+    ("month", "months"),
+    ("year", "years"),
+    ("inch", "inches"),
+    ("cup", "cups"),
+    ("acre", "acres"),
+    ("bit", "bits"),
+];
+
+/// One unit raised to a power, e.g. the `s^2` in `m/s^2`. The power is
+/// small enough that adding up dimensions can't overflow.
+#[derive(Debug, Clone, PartialEq)]
+struct Term {
+    symbol: String,
+    factor: Number,
+    offset: Number,
+    dimension: Dimension,
+    power: i8,
+}
+
+/// A product of units, like `km`, `km/h` or `kg*m/s^2`. The default unit has
+/// no terms and is dimensionless.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Unit {
+    terms: Vec<Term>,
+}
+
+impl Unit {
+    /// Looks up a unit by its symbol or name, with an optional prefix, like
+    /// `m`, `km`, `kilometers` or `GiB`.
+    pub fn lookup(name: &str) -> Option<Unit> {
+        let lowercase = name.to_lowercase();
+
+        for definition in DEFINITIONS {
+            if definition.symbols.contains(&name) || definition.names.contains(&lowercase.as_str()) {
+                return Some(Unit::from_definition(definition, definition.symbols[0].to_string(), 1.0));
+            }
+        }
+
+        if let Some(code) = currency::lookup(name).or_else(|| currency::lookup_name(name)) {
+            return Some(Unit::from_currency(code));
+        }
+
+        for definition in DEFINITIONS {
+            let prefixes = match definition.prefixes {
+                Prefixes::None => continue,
+                Prefixes::Metric => METRIC_PREFIXES,
+                Prefixes::Data => DATA_PREFIXES,
+            };
+
+            for (prefix, long_prefix, scale) in prefixes {
+                let symbol = name.strip_prefix(prefix)
+                    .filter(|rest| definition.symbols.contains(rest));
+                let long_name = lowercase.strip_prefix(long_prefix)
+                    .filter(|rest| definition.names.contains(rest));
+
+                if symbol.is_some() || long_name.is_some() {
+                    let symbol = format!("{}{}", prefix, definition.symbols[0]);
+                    return Some(Unit::from_definition(definition, symbol, *scale));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn from_definition(definition: &Definition, symbol: String, scale: f64) -> Unit {
+        let number = |value| Number::from_f64(value).unwrap_or_default();
+
+        let term = Term {
+            symbol,
+            factor: number(definition.factor) * number(scale),
+            offset: number(definition.offset),
+            dimension: definition.dimension,
+            power: 1,
+        };
+
+        Unit { terms: vec![term] }
+    }
+
+    /// All currencies share the money dimension, converting between them
+    /// goes through exchange rates instead of a fixed factor.
+    pub fn from_currency(code: &str) -> Unit {
+        let term = Term {
+            symbol: code.to_string(),
+            factor: Number::from(1),
+            offset: Number::default(),
+            dimension: MONEY,
+            power: 1,
+        };
+
+        Unit { terms: vec![term] }
+    }
+
+    /// The ISO code of the currency in this unit, like `USD` in `USD/h`.
+    pub fn currency(&self) -> Option<&str> {
+        self.currency_term().map(|term| term.symbol.as_str())
+    }
+
+    /// Whether this is a plain currency, like `EUR`.
+    pub fn is_currency(&self) -> bool {
+        matches!(self.terms.as_slice(), [term] if term.dimension == MONEY && term.power == 1)
+    }
+
+    fn currency_term(&self) -> Option<&Term> {
+        self.terms.iter().find(|term| term.dimension == MONEY)
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        self.terms.iter()
+            .fold(NONE, |dimension, term| dimension.combine(term.dimension, term.power))
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.dimension().is_none()
+    }
+
+    pub fn is_time(&self) -> bool {
+        self.dimension() == TIME
+    }
+
+    /// Scale of this unit relative to the SI base units of its dimension, or
+    /// `None` when it's too large for a float.
+    pub fn factor(&self) -> Option<Number> {
+        self.terms.iter()
+            .try_fold(Number::from(1), |product, term| Some(product * term.factor.powi(i32::from(term.power))?))
+    }
+
+    /// Only a plain temperature scale like `°C` has a zero point that differs
+    /// from the base unit; in compound units it behaves like a difference.
+    fn offset(&self) -> Number {
+        match self.terms.as_slice() {
+            [term] if term.power == 1 => term.offset.clone(),
+            _ => Number::default(),
+        }
+    }
+
+    /// Multiplies two units, returning `None` when an exponent gets too
+    /// large, like in `m^100 * m^100`.
+    pub fn multiply(&self, other: &Unit) -> Option<Unit> {
+        let mut terms = self.terms.clone();
+
+        for term in &other.terms {
+            match terms.iter_mut().find(|t| t.symbol == term.symbol) {
+                Some(existing) => existing.power = existing.power.checked_add(term.power)?,
+                None => terms.push(term.clone()),
+            }
+        }
+
+        terms.retain(|term| term.power != 0);
+
+        Some(Unit { terms })
+    }
+
+    pub fn divide(&self, other: &Unit) -> Option<Unit> {
+        self.multiply(&other.pow(-1)?)
+    }
+
+    /// Raises a unit to a power, returning `None` when an exponent gets too
+    /// large, like in `m^256`.
+    pub fn pow(&self, power: i32) -> Option<Unit> {
+        let power = i8::try_from(power).ok()?;
+
+        let terms = self.terms.iter()
+            .map(|term| Some(Term { power: term.power.checked_mul(power)?, ..term.clone() }))
+            .collect::<Option<_>>()?;
+
+        Some(Unit { terms })
+    }
+
+    /// The unit as it's written after `amount`, with a word like `day` in
+    /// the plural unless the amount is one.
+    fn after(&self, amount: &Number) -> String {
+        let plural = match self.terms.as_slice() {
+            [term] if term.power == 1 && amount.abs() != Number::from(1) => {
+                PLURALS.iter().find(|(symbol, _)| *symbol == term.symbol)
+            }
+            _ => None,
+        };
+
+        match plural {
+            Some((_, plural)) => plural.to_string(),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_term = |term: &Term, power: u8| {
+            if power == 1 {
+                term.symbol.clone()
+            } else {
+                format!("{}^{}", term.symbol, power)
+            }
+        };
+
+        let numerator: Vec<_> = self.terms.iter()
+            .filter(|term| term.power > 0)
+            .map(|term| format_term(term, term.power.unsigned_abs()))
+            .collect();
+        let denominator: Vec<_> = self.terms.iter()
+            .filter(|term| term.power < 0)
+            .map(|term| format_term(term, term.power.unsigned_abs()))
+            .collect();
+
+        if numerator.is_empty() {
+            write!(f, "1")?;
+        } else {
+            write!(f, "{}", numerator.join("*"))?;
+        }
+
+        for term in denominator {
+            write!(f, "/{term}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A number with a unit attached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    value: Number,
+    unit: Unit,
+}
+
+impl Quantity {
+    pub fn new(value: Number, unit: Unit) -> Self {
+        Self { value, unit }
+    }
+
+    pub fn value(&self) -> &Number {
+        &self.value
+    }
+
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+
+    /// Returns the same amount expressed in `unit`, or `None` when the
+    /// dimensions don't match.
+    pub fn convert(&self, unit: &Unit) -> Option<Quantity> {
+        if self.unit == *unit {
+            return Some(self.clone());
+        }
+
+        if self.unit.dimension() != unit.dimension() {
+            return None;
+        }
+
+        // Different currencies share a dimension but need an exchange rate.
+        if self.unit.currency() != unit.currency() {
+            return None;
+        }
+
+        let value = (self.base_value()? - unit.offset()).checked_div(&unit.factor()?)?;
+
+        Some(Quantity::new(value, unit.clone()))
+    }
+
+    /// Swaps the currency in the unit for `currency`, where one unit of the
+    /// current currency is worth `rate` of the new one.
+    pub fn exchange(&self, currency: &str, rate: &Number) -> Option<Quantity> {
+        let mut unit = self.unit.clone();
+
+        let Some(term) = unit.terms.iter_mut().find(|term| term.dimension == MONEY) else {
+            return Some(self.clone());
+        };
+
+        let value = self.value.clone() * rate.powi(i32::from(term.power))?;
+        term.symbol = currency.to_string();
+
+        Some(Quantity::new(value, unit))
+    }
+
+    /// The value in SI base units, used when all units cancel out, or `None`
+    /// when it's too large for a float.
+    pub fn base_value(&self) -> Option<Number> {
+        Some(self.value.clone() * self.unit.factor()? + self.unit.offset())
+    }
+}
+
+/// Currencies always show cents, other quantities pass the precision on to
+/// the value.
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.unit.is_currency() {
+            return write!(f, "{} {}", self.value.fixed(2), self.unit);
+        }
+
+        let unit = self.unit.after(&self.value);
+
+        match f.precision() {
+            Some(places) => write!(f, "{:.*} {}", places, self.value, unit),
+            None => write!(f, "{} {}", self.value, unit),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::calc::compiler::{self, Chunk};
+use crate::calc::date::{Date, Time};
+use crate::calc::number::{Notation, Number};
+use crate::calc::parser::{Atom, Expression};
+use crate::calc::units::Quantity;
+
+/// What an expression evaluates to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Number(Number),
+    Percentage(Number),
+    /// A number that is shown as a fraction, like `3/8`.
+    Fraction(Number, Notation),
+    Quantity(Quantity),
+    /// A length of time in seconds, shown in hours and minutes.
+    Duration(Number),
+    Date(Date),
+    Time(Time),
+    Text(String),
+}
+
+impl Value {
+    /// The value of a literal, which is `None` for a name that still has to
+    /// be looked up.
+    pub fn from_atom(atom: &Atom) -> Option<Value> {
+        match atom {
+            Atom::Boolean(boolean) => Some(Value::Boolean(*boolean)),
+            Atom::Number(number) => Some(Value::Number(number.clone())),
+            Atom::Quantity(quantity) => Some(Value::Quantity(quantity.clone())),
+            Atom::Duration(seconds) => Some(Value::Duration(seconds.clone())),
+            Atom::Date(date) => Some(Value::Date(*date)),
+            Atom::Time(time) => Some(Value::Time(*time)),
+            Atom::Name(_) => None,
+        }
+    }
+}
+
+/// A function defined in the sheet, like `area(w, h): w * h`.
+#[derive(Debug, PartialEq)]
+pub struct Definition {
+    pub parameters: Vec<String>,
+    pub body: Expression,
+    /// The body compiled, for calls from compiled code.
+    pub chunk: Chunk,
+}
+
+impl Definition {
+    pub fn new(parameters: Vec<String>, body: Expression) -> Self {
+        let chunk = compiler::compile(&body);
+
+        Self { parameters, body, chunk }
+    }
+}
+
+/// What a name in the sheet stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    Value(Value),
+    Function(Rc<Definition>),
+}
+
+/// The names defined so far, which the lines below can use.
+pub type Context = HashMap<String, Binding>;
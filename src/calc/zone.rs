@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::calc::date;
+
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+/// Abbreviations people use for the time of a region, which mostly aren't
+/// zone names of their own. They follow daylight saving time, so `PST` is
+/// whatever time it is in Los Angeles.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("PST", "America/Los_Angeles"),
+    ("PDT", "America/Los_Angeles"),
+    ("MST", "America/Denver"),
+    ("MDT", "America/Denver"),
+    ("CST", "America/Chicago"),
+    ("CDT", "America/Chicago"),
+    ("EST", "America/New_York"),
+    ("EDT", "America/New_York"),
+    ("AKST", "America/Anchorage"),
+    ("HST", "Pacific/Honolulu"),
+    ("BRT", "America/Sao_Paulo"),
+    ("BST", "Europe/London"),
+    ("CEST", "CET"),
+    ("EEST", "EET"),
+    ("WEST", "WET"),
+    ("IST", "Asia/Kolkata"),
+    ("SGT", "Asia/Singapore"),
+    ("JST", "Asia/Tokyo"),
+    ("KST", "Asia/Seoul"),
+    ("AEST", "Australia/Sydney"),
+    ("AEDT", "Australia/Sydney"),
+    ("NZST", "Pacific/Auckland"),
+    ("NZDT", "Pacific/Auckland"),
+];
+
+/// The directories of the tz database that hold cities, so `Tokyo` can be
+/// found as `Asia/Tokyo`.
+const REGIONS: &[&str] = &[
+    "Africa", "America", "Antarctica", "Arctic", "Asia", "Atlantic", "Australia", "Europe", "Indian", "Pacific",
+];
+
+/// A time zone from the system tz database, which knows the offset from UTC
+/// at any moment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    /// When each offset starts, in seconds since 1970 UTC, oldest first.
+    transitions: Vec<(i64, i64)>,
+    /// The offset before the first transition.
+    initial: i64,
+    /// The offsets after the last transition, from the footer of the file.
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    pub fn utc() -> Zone {
+        Zone { transitions: vec![], initial: 0, rule: None }
+    }
+
+    /// Finds a zone by its name like `America/New_York` or `CET`, a city
+    /// like `Tokyo` or `New York`, or a common abbreviation like `PST`.
+    pub fn lookup(name: &str) -> Option<Zone> {
+        if name.contains("..") || name.starts_with('/') {
+            return None;
+        }
+
+        if name.eq_ignore_ascii_case("local") {
+            return Some(Zone::local());
+        }
+
+        let upper = name.to_uppercase();
+
+        if let Some((_, path)) = ABBREVIATIONS.iter().find(|(abbreviation, _)| *abbreviation == upper) {
+            return Zone::load(&Path::new(ZONEINFO).join(path));
+        }
+
+        let name = name.replace(' ', "_");
+
+        for candidate in [&name, &upper] {
+            let path = Path::new(ZONEINFO).join(candidate);
+
+            if path.is_file() {
+                return Zone::load(&path);
+            }
+        }
+
+        Zone::load(cities().get(&name.to_lowercase())?)
+    }
+
+    /// The zone of this computer, from `TZ` or `/etc/localtime`, falling back
+    /// to UTC.
+    pub fn local() -> Zone {
+        if let Ok(tz) = std::env::var("TZ") {
+            let tz = tz.strip_prefix(':').unwrap_or(&tz);
+
+            if !tz.is_empty() {
+                let path = Path::new(ZONEINFO).join(tz);
+
+                if let Some(zone) = Zone::load(&path) {
+                    return zone;
+                }
+
+                if let Some(rule) = Rule::parse(tz) {
+                    return Zone { transitions: vec![], initial: rule.standard, rule: Some(rule) };
+                }
+            }
+        }
+
+        Zone::load(Path::new("/etc/localtime")).unwrap_or_else(Zone::utc)
+    }
+
+    fn load(path: &Path) -> Option<Zone> {
+        Zone::parse(&std::fs::read(path).ok()?)
+    }
+
+    /// Reads a TZif file as described in RFC 8536, preferring the 64-bit
+    /// data of version 2 and later.
+    fn parse(data: &[u8]) -> Option<Zone> {
+        let mut reader = Reader { data };
+
+        let (version, mut counts) = reader.header()?;
+        let mut time_size = 4;
+
+        if version >= b'2' {
+            reader.skip(counts.length(4))?;
+            counts = reader.header()?.1;
+            time_size = 8;
+        }
+
+        let times: Vec<i64> = (0..counts.times).map(|_| reader.integer(time_size)).collect::<Option<_>>()?;
+        let indices = reader.take(counts.times)?.to_vec();
+        let offsets: Vec<i64> = (0..counts.types)
+            .map(|_| {
+                let offset = reader.integer(4)?;
+                reader.skip(2)?;
+                Some(offset)
+            })
+            .collect::<Option<_>>()?;
+
+        reader.skip(counts.characters + counts.leaps * (time_size + 4) + counts.standard + counts.universal)?;
+
+        let transitions = times.into_iter()
+            .zip(indices)
+            .map(|(time, index)| Some((time, *offsets.get(index as usize)?)))
+            .collect::<Option<_>>()?;
+
+        // The footer is a POSIX `TZ` string between newlines.
+        let footer = std::str::from_utf8(reader.data).ok()
+            .and_then(|footer| footer.trim_matches('\n').lines().next())
+            .and_then(Rule::parse);
+
+        Some(Zone { transitions, initial: *offsets.first()?, rule: footer })
+    }
+
+    /// Seconds east of UTC at a moment given in UTC.
+    pub fn offset_at(&self, utc: i64) -> i64 {
+        let index = self.transitions.partition_point(|(start, _)| *start <= utc);
+
+        match (index, &self.rule) {
+            (index, Some(rule)) if index == self.transitions.len() => rule.offset_at(utc),
+            (0, _) => self.initial,
+            (index, _) => self.transitions[index - 1].1,
+        }
+    }
+
+    /// The moment in UTC of a wall clock time in this zone. Times that
+    /// daylight saving skips or repeats use the offset from before the
+    /// change.
+    pub fn to_utc(&self, wall: i64) -> i64 {
+        let guess = wall - self.offset_at(wall);
+        wall - self.offset_at(guess)
+    }
+}
+
+/// The files of the tz database's cities by their lowercase names, read
+/// once since every word that might be a city is looked up as one.
+fn cities() -> &'static HashMap<String, PathBuf> {
+    static CITIES: OnceLock<HashMap<String, PathBuf>> = OnceLock::new();
+
+    CITIES.get_or_init(|| {
+        let mut cities = HashMap::new();
+
+        for region in REGIONS {
+            find_cities(&Path::new(ZONEINFO).join(region), &mut cities);
+        }
+
+        cities
+    })
+}
+
+/// Adds the cities in a directory of the tz database, since some regions
+/// like `America/Argentina` have their own directories. The first city of a
+/// name wins.
+fn find_cities(directory: &Path, cities: &mut HashMap<String, PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.is_dir() {
+            find_cities(&path, cities);
+        } else {
+            cities.entry(entry.file_name().to_string_lossy().to_lowercase()).or_insert(path);
+        }
+    }
+}
+
+/// The number of each kind of record in a TZif block.
+#[derive(Clone, Copy)]
+struct Counts {
+    universal: usize,
+    standard: usize,
+    leaps: usize,
+    times: usize,
+    types: usize,
+    characters: usize,
+}
+
+impl Counts {
+    /// The size of the data after the header, for times of `time_size`
+    /// bytes.
+    fn length(&self, time_size: usize) -> usize {
+        self.times * (time_size + 1)
+            + self.types * 6
+            + self.characters
+            + self.leaps * (time_size + 4)
+            + self.standard
+            + self.universal
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.data.len() < length {
+            return None;
+        }
+
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+
+        Some(taken)
+    }
+
+    fn skip(&mut self, length: usize) -> Option<()> {
+        self.take(length).map(|_| ())
+    }
+
+    /// A signed big-endian integer of 4 or 8 bytes.
+    fn integer(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.take(size)?;
+
+        match size {
+            4 => Some(i32::from_be_bytes(bytes.try_into().ok()?) as i64),
+            _ => Some(i64::from_be_bytes(bytes.try_into().ok()?)),
+        }
+    }
+
+    fn header(&mut self) -> Option<(u8, Counts)> {
+        let header = self.take(44)?;
+
+        if !header.starts_with(b"TZif") {
+            return None;
+        }
+
+        let count = |index: usize| {
+            let bytes = header[20 + index * 4..24 + index * 4].try_into().ok()?;
+            usize::try_from(u32::from_be_bytes(bytes)).ok()
+        };
+
+        let counts = Counts {
+            universal: count(0)?,
+            standard: count(1)?,
+            leaps: count(2)?,
+            times: count(3)?,
+            types: count(4)?,
+            characters: count(5)?,
+        };
+
+        Some((header[4], counts))
+    }
+}
+
+/// A POSIX `TZ` rule like `EST5EDT,M3.2.0,M11.1.0`, which is how the tz
+/// database describes the years after its last transition.
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    /// Seconds east of UTC outside of daylight saving time.
+    standard: i64,
+    daylight: Option<Daylight>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Daylight {
+    offset: i64,
+    start: Change,
+    end: Change,
+}
+
+/// When daylight saving starts or ends, like `M3.2.0/2` for 2 am on the
+/// second Sunday of March.
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    month: u32,
+    week: i64,
+    weekday: i64,
+    /// Local time of day, which may be negative or past midnight.
+    time: i64,
+}
+
+impl Rule {
+    fn parse(text: &str) -> Option<Rule> {
+        let mut text = text;
+
+        skip_name(&mut text)?;
+        let standard = -parse_offset(&mut text)?;
+
+        if text.is_empty() {
+            return Some(Rule { standard, daylight: None });
+        }
+
+        skip_name(&mut text)?;
+
+        let offset = match text.starts_with(',') {
+            true => standard + 3600,
+            false => -parse_offset(&mut text)?,
+        };
+
+        let mut changes = text.strip_prefix(',')?.split(',').map(Change::parse);
+        let (start, end) = (changes.next()??, changes.next()??);
+
+        Some(Rule { standard, daylight: Some(Daylight { offset, start, end }) })
+    }
+
+    fn offset_at(&self, utc: i64) -> i64 {
+        let Some(daylight) = &self.daylight else {
+            return self.standard;
+        };
+
+        let (year, _, _) = date::civil_from_days((utc + self.standard).div_euclid(86_400));
+        let start = daylight.start.wall_time(year) - self.standard;
+        let end = daylight.end.wall_time(year) - daylight.offset;
+
+        // In the southern hemisphere daylight saving spans the new year.
+        let is_daylight = match start < end {
+            true => (start..end).contains(&utc),
+            false => !(end..start).contains(&utc),
+        };
+
+        if is_daylight { daylight.offset } else { self.standard }
+    }
+}
+
+impl Change {
+    /// Only the `Mm.w.d` form, which is the one the tz database uses.
+    fn parse(text: &str) -> Option<Change> {
+        let (date, time) = match text.split_once('/') {
+            Some((date, time)) => (date, Some(time)),
+            None => (text, None),
+        };
+
+        let mut fields = date.strip_prefix('M')?.split('.').map(|field| field.parse::<i64>().ok());
+        let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || !(0..=6).contains(&weekday) {
+            return None;
+        }
+
+        let time = match time {
+            Some(mut time) => parse_offset(&mut time)?,
+            None => 2 * 3600,
+        };
+
+        Some(Change { month: month as u32, week, weekday, time })
+    }
+
+    /// Seconds since 1970 on the local wall clock, in `year`.
+    fn wall_time(&self, year: i64) -> i64 {
+        let first = date::days_from_civil(year, self.month, 1);
+        let length = date::days_in_month(year, self.month) as i64;
+
+        // 1970-01-01 was a Thursday, and Sunday is 0.
+        let mut day = (self.weekday - (first + 4)).rem_euclid(7) + (self.week - 1) * 7;
+
+        // Week 5 means the last one.
+        while day >= length {
+            day -= 7;
+        }
+
+        (first + day) * 86_400 + self.time
+    }
+}
+
+/// Skips a zone abbreviation like `EST` or `<+09>`.
+fn skip_name(text: &mut &str) -> Option<()> {
+    let length = match text.strip_prefix('<') {
+        Some(rest) => rest.find('>')? + 2,
+        None => text.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(text.len()),
+    };
+
+    if length < 3 {
+        return None;
+    }
+
+    *text = &text[length..];
+
+    Some(())
+}
+
+/// Reads `[+-]hh[:mm[:ss]]` as seconds. For offsets, POSIX counts hours west
+/// of UTC.
+fn parse_offset(text: &mut &str) -> Option<i64> {
+    let sign = match text.chars().next()? {
+        '-' => -1,
+        _ => 1,
+    };
+
+    let rest = text.trim_start_matches(['+', '-']);
+    let length = rest.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(rest.len());
+
+    let mut seconds = 0;
+
+    for (index, part) in rest[..length].split(':').enumerate().take(3) {
+        seconds += part.parse::<i64>().ok()? * [3600, 60, 1][index];
+    }
+
+    *text = &rest[length..];
+
+    Some(sign * seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EST: i64 = -5 * 3600;
+    const EDT: i64 = -4 * 3600;
+
+    /// A TZif file of `version` with 64-bit `transitions` between `offsets`,
+    /// as `(start, index into offsets)`, and a `footer` rule. Version 1 files
+    /// only have 32-bit data. Later ones have 32-bit data too, which is
+    /// written with the wrong offsets to show it's skipped.
+    fn tzif(version: u8, transitions: &[(i64, u8)], offsets: &[i32], footer: &str) -> Vec<u8> {
+        let block = |data: &mut Vec<u8>, time_size: usize, offsets: &[i32]| {
+            data.extend(b"TZif");
+            data.push(version);
+            data.extend([0; 15]);
+
+            for count in [0, 0, 0, transitions.len(), offsets.len(), 4] {
+                data.extend((count as u32).to_be_bytes());
+            }
+
+            for (start, _) in transitions {
+                match time_size {
+                    4 => data.extend((*start as i32).to_be_bytes()),
+                    _ => data.extend(start.to_be_bytes()),
+                }
+            }
+
+            data.extend(transitions.iter().map(|(_, index)| index));
+
+            for offset in offsets {
+                data.extend(offset.to_be_bytes());
+                data.extend([0, 0]);
+            }
+
+            data.extend(b"UTC\0");
+        };
+
+        let mut data = vec![];
+
+        if version == b'1' {
+            block(&mut data, 4, offsets);
+            return data;
+        }
+
+        block(&mut data, 4, &vec![0; offsets.len()]);
+        block(&mut data, 8, offsets);
+        data.extend(format!("\n{footer}\n").bytes());
+
+        data
+    }
+
+    /// Seconds since 1970 UTC.
+    fn utc(year: i64, month: u32, day: u32, hour: i64) -> i64 {
+        date::days_from_civil(year, month, day) * 86_400 + hour * 3600
+    }
+
+    #[test]
+    fn version_1() {
+        let data = tzif(b'1', &[(utc(2000, 1, 1, 0), 1), (utc(2010, 1, 1, 0), 2)], &[0, 3600, 7200], "");
+        let zone = Zone::parse(&data).unwrap();
+
+        assert_eq!(zone.offset_at(utc(1999, 12, 31, 23)), 0);
+        assert_eq!(zone.offset_at(utc(2000, 1, 1, 0)), 3600);
+        assert_eq!(zone.offset_at(utc(2009, 6, 1, 0)), 3600);
+        assert_eq!(zone.offset_at(utc(2030, 6, 1, 0)), 7200);
+    }
+
+    #[test]
+    fn version_2_with_daylight_saving() {
+        // The table covers 2025, and the footer the years after it.
+        let transitions = [(utc(2025, 3, 9, 7), 1), (utc(2025, 11, 2, 6), 0)];
+        let data = tzif(b'2', &transitions, &[EST as i32, EDT as i32], "EST5EDT,M3.2.0,M11.1.0");
+        let zone = Zone::parse(&data).unwrap();
+
+        assert_eq!(zone.offset_at(utc(2025, 1, 1, 0)), EST);
+        assert_eq!(zone.offset_at(utc(2025, 7, 1, 0)), EDT);
+        assert_eq!(zone.offset_at(utc(2025, 12, 1, 0)), EST);
+
+        // 2 am on the second Sunday of March and the first of November.
+        assert_eq!(zone.offset_at(utc(2026, 3, 8, 7) - 1), EST);
+        assert_eq!(zone.offset_at(utc(2026, 3, 8, 7)), EDT);
+        assert_eq!(zone.offset_at(utc(2026, 11, 1, 6) - 1), EDT);
+        assert_eq!(zone.offset_at(utc(2026, 11, 1, 6)), EST);
+
+        assert_eq!(zone.to_utc(utc(2026, 7, 1, 12)), utc(2026, 7, 1, 16));
+        assert_eq!(zone.to_utc(utc(2026, 1, 1, 12)), utc(2026, 1, 1, 17));
+    }
+
+    #[test]
+    fn southern_daylight_saving() {
+        let data = tzif(b'2', &[], &[36_000], "AEST-10AEDT,M10.1.0,M4.1.0/3");
+        let zone = Zone::parse(&data).unwrap();
+
+        assert_eq!(zone.offset_at(utc(2026, 1, 15, 0)), 39_600);
+        assert_eq!(zone.offset_at(utc(2026, 7, 15, 0)), 36_000);
+    }
+
+    #[test]
+    fn broken_files() {
+        let data = tzif(b'2', &[(utc(2025, 3, 9, 7), 1)], &[EST as i32, EDT as i32], "EST5EDT,M3.2.0,M11.1.0");
+
+        assert_eq!(Zone::parse(&data[..data.len() / 2]), None);
+        assert_eq!(Zone::parse(b"not a TZif file at all, but long enough for a header"), None);
+    }
+}
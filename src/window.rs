@@ -1,18 +1,20 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::collections::HashMap;
-
+use std::cell::{Cell, RefCell};
 use adw::subclass::prelude::*;
 use gtk::prelude::*;
 use gtk::{gio, glib};
-use gsv;
+use gsv::prelude::*;
 
 use gettextrs::gettext;
 
 use crate::application::Application;
 use crate::config::{APP_ID, PROFILE, VERSION};
 
-use crate::calc::{lexer::Lexer, parser::{Parser, Atom, Expression}, interpreter::Interpreter};
+use crate::calc::{compiler::Cache, currency::Rates, date, error::{Error, Span}, lexer, locale::Separators, interpreter::Interpreter, references, value::{Context, Value}};
+
+/// A place in the text buffer, as a line and a byte index into it.
+type Position = (i32, i32);
 
 mod imp {
     use super::*;
@@ -21,7 +23,31 @@ mod imp {
     #[template(resource = "/com/felipekinoshita/Count/ui/window.ui")]
     pub struct Window {
         pub settings: gio::Settings,
-        pub context: HashMap<String, Expression>,
+        pub context: Context,
+        pub rates: Rates,
+        /// The result of each line and every error found on it. A line with
+        /// errors can still have a result, from what could be made of it.
+        pub results: RefCell<Vec<(String, Vec<Error>)>>,
+        /// The lines compiled the last time the sheet changed.
+        pub compiled: RefCell<Cache>,
+        /// Lines that moved with the last edit, as the first line that moved
+        /// and by how much, so `lineN` references can follow them.
+        pub moved_lines: Cell<Option<(i32, i32)>>,
+        /// Where the text of the last edit went, from and to a line and byte
+        /// index. Its `lineN` references already point where they should.
+        pub inserted: Cell<Option<(Position, Position)>>,
+        /// Whether the user is in the middle of an edit, as opposed to the
+        /// text being set or changed by undo and redo.
+        pub user_action: Cell<bool>,
+        /// Whether an undo or redo is being applied.
+        pub undoing: Cell<bool>,
+        /// Whether `lineN` references are being rewritten, which changes the
+        /// text without moving any lines.
+        pub renumbering: Cell<bool>,
+        pub results_renderer: gsv::GutterRendererText,
+        pub error_tag: gtk::TextTag,
+        pub comment_tag: gtk::TextTag,
+        pub heading_tag: gtk::TextTag,
 
         #[template_child]
         pub text_view: TemplateChild<gsv::View>,
@@ -33,7 +59,30 @@ mod imp {
         fn default() -> Self {
             Self {
                 settings: gio::Settings::new(APP_ID),
-                context: HashMap::new(),
+                context: Context::new(),
+                rates: Rates::load(&glib::user_config_dir().join("count").join("rates")).unwrap_or_default(),
+                results: RefCell::default(),
+                compiled: RefCell::default(),
+                moved_lines: Cell::default(),
+                inserted: Cell::default(),
+                user_action: Cell::default(),
+                undoing: Cell::default(),
+                renumbering: Cell::default(),
+                results_renderer: gsv::GutterRendererText::new(),
+                error_tag: gtk::TextTag::builder()
+                    .name("error")
+                    .underline(gtk::pango::Underline::Error)
+                    .build(),
+                comment_tag: gtk::TextTag::builder()
+                    .name("comment")
+                    .foreground("#9a9996")
+                    .style(gtk::pango::Style::Italic)
+                    .build(),
+                heading_tag: gtk::TextTag::builder()
+                    .name("heading")
+                    .weight(700)
+                    .scale(1.2)
+                    .build(),
 
                 text_view: TemplateChild::default(),
                 text_buffer: TemplateChild::default(),
@@ -70,6 +119,10 @@ mod imp {
             if PROFILE == "Devel" {
                 obj.add_css_class("devel");
             }
+
+            obj.setup_results_gutter();
+            obj.setup_diagnostics();
+            obj.setup_line_references();
         }
     }
 
@@ -77,15 +130,55 @@ mod imp {
     impl Window {
         #[template_callback]
         fn on_text_changed(&self, text_buffer: gsv::Buffer) {
+            // The sheet is evaluated once the references are right.
+            if self.renumbering.get() {
+                return;
+            }
+
+            if let Some((first, delta)) = self.moved_lines.take() {
+                self.obj().renumber_line_references(first, delta, self.inserted.take());
+            }
+
             let input = text_buffer.text(&text_buffer.start_iter(), &text_buffer.end_iter(), true);
             let lines: Vec<_> = input.split("\n").collect();
 
-            let mut context = self.obj().imp().context.clone();
+            let mut context = self.context.clone();
+            let mut interpreter = Interpreter::with_rates(self.rates.clone());
+            let separators = Separators::for_sheet(&input);
+            let mut compiled = self.compiled.borrow_mut();
+            let mut results = vec![];
+
+            for (index, line) in lines.iter().enumerate() {
+                if line.trim().is_empty() || lexer::is_heading(line) {
+                    interpreter.end_block();
+                }
+
+                let (value, errors) = match Separators::is_directive(line) {
+                    true => (None, vec![]),
+                    false => self.obj().evaluate(line, separators, &mut compiled, &mut interpreter, &mut context),
+                };
+
+                interpreter.record_line(value.as_ref());
 
-            for line in lines.iter() {
-                let value = self.obj().evaluate(line.to_string(), &mut context);
-                println!("{value}");
+                if let Some(value) = &value {
+                    references::record(&mut context, index, value.clone());
+                }
+
+                let result = match value {
+                    Some(value) => self.obj().format(value, separators),
+                    None => String::new(),
+                };
+
+                results.push((result, errors));
             }
+
+            compiled.sweep();
+            drop(compiled);
+
+            self.results.replace(results);
+            self.obj().update_results_gutter();
+            self.obj().update_diagnostics();
+            self.obj().update_comments(&lines);
         }
     }
 
@@ -109,47 +202,292 @@ impl Window {
             .build()
     }
 
-    fn evaluate(&self, input: String, context: &mut HashMap<String, Expression>) -> String {
-        let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+    fn setup_results_gutter(&self) {
+        let imp = self.imp();
+        let renderer = &imp.results_renderer;
 
-        let mut parser = Parser::new(tokens);
-        let expressions = parser.parse();
+        renderer.set_xalign(1.0);
+        renderer.set_xpad(12);
+        renderer.set_alignment_mode(gsv::GutterRendererAlignmentMode::First);
+        renderer.add_css_class("results");
 
-        let mut interpreter = Interpreter::new();
+        renderer.connect_query_data(glib::clone!(@weak self as obj => move |renderer, _, line| {
+            let results = obj.imp().results.borrow();
+            let result = match results.get(line as usize) {
+                Some((result, _)) => result.as_str(),
+                None => "",
+            };
 
-        let mut return_value = String::new();
+            renderer.set_text(result);
+        }));
 
-        for expr in expressions {
-            let evaluated = interpreter.evaluate(expr, context);
-            let value = interpreter.literal_value(evaluated);
+        let gutter = gsv::prelude::ViewExt::gutter(&*imp.text_view, gtk::TextWindowType::Right);
+        gutter.insert(renderer, 0);
+    }
 
-            let value = match value {
-                Some(Atom::Boolean(boolean)) => {
-                    boolean.to_string()
-                },
-                Some(Atom::Number(number)) => {
-                    number.to_string()
-                },
-                Some(Atom::Name(name)) => {
-                    let e = interpreter.evaluate(Expression::Literal(Atom::Name(name.clone())), context);
-                    let v = interpreter.literal_value(e);
+    fn update_results_gutter(&self) {
+        let imp = self.imp();
+        let renderer = &imp.results_renderer;
+
+        // The gutter doesn't size itself from the text of each line, so make
+        // room for the widest result and let xalign do the right-alignment.
+        let width = imp.results.borrow().iter()
+            .map(|(result, _)| GutterRendererTextExt::measure(renderer, result).0)
+            .max()
+            .unwrap_or(0);
 
-                    let x = match v {
-                        Some(Atom::Boolean(boolean)) => format!("{boolean}").to_string(),
-                        Some(Atom::Number(number)) => format!("{number}").to_string(),
-                        _ => "".to_string(),
-                    };
+        renderer.set_width_request(width + renderer.xpad() * 2);
+        renderer.queue_draw();
+    }
+
+    fn setup_diagnostics(&self) {
+        let imp = self.imp();
+
+        imp.text_buffer.tag_table().add(&imp.error_tag);
+        imp.text_buffer.tag_table().add(&imp.comment_tag);
+        imp.text_buffer.tag_table().add(&imp.heading_tag);
+
+        imp.text_view.set_has_tooltip(true);
+        imp.text_view.connect_query_tooltip(glib::clone!(@weak self as obj => @default-return false, move |view, x, y, keyboard_mode, tooltip| {
+            let iter = if keyboard_mode {
+                let buffer = view.buffer();
+                buffer.iter_at_mark(&buffer.get_insert())
+            } else {
+                let (x, y) = view.window_to_buffer_coords(gtk::TextWindowType::Widget, x, y);
+                match view.iter_at_location(x, y) {
+                    Some(iter) => iter,
+                    None => return false,
+                }
+            };
+
+            let imp = obj.imp();
+
+            if !iter.has_tag(&imp.error_tag) {
+                return false;
+            }
+
+            let results = imp.results.borrow();
+            let Some((_, errors)) = results.get(iter.line() as usize) else {
+                return false;
+            };
 
-                    x.to_string()
+            let index = iter.line_index() as usize;
+            let error = errors.iter()
+                .find(|error| (error.span().start..=error.span().end).contains(&index))
+                .or(errors.first());
+
+            match error {
+                Some(error) => {
+                    tooltip.set_text(Some(error.message()));
+                    true
                 },
-                None => "".to_string(),
+                None => false,
+            }
+        }));
+    }
+
+    fn update_diagnostics(&self) {
+        let imp = self.imp();
+        let buffer = &*imp.text_buffer;
+
+        buffer.remove_tag(&imp.error_tag, &buffer.start_iter(), &buffer.end_iter());
+
+        for (line, (_, errors)) in imp.results.borrow().iter().enumerate() {
+            for error in errors {
+                self.underline(line as i32, error);
+            }
+        }
+    }
+
+    fn underline(&self, line: i32, error: &Error) {
+        let imp = self.imp();
+        let buffer = &*imp.text_buffer;
+
+        let span = error.span();
+        let start = buffer.iter_at_line_index(line, span.start as i32);
+        let end = buffer.iter_at_line_index(line, span.end as i32);
+
+        if let (Some(mut start), Some(end)) = (start, end) {
+            // Errors at the end of a line (like a missing operand) have an
+            // empty span, so underline the character before them instead.
+            if start == end && !start.starts_line() {
+                start.backward_char();
+            }
+
+            buffer.apply_tag(&imp.error_tag, &start, &end);
+        }
+    }
+
+    fn update_comments(&self, lines: &[&str]) {
+        let imp = self.imp();
+        let buffer = &*imp.text_buffer;
+
+        buffer.remove_tag(&imp.comment_tag, &buffer.start_iter(), &buffer.end_iter());
+        buffer.remove_tag(&imp.heading_tag, &buffer.start_iter(), &buffer.end_iter());
+
+        for (line, text) in lines.iter().enumerate() {
+            let Some(start) = lexer::comment_start(text) else {
+                continue;
+            };
+
+            let tag = match lexer::is_heading(text) {
+                true => &imp.heading_tag,
+                false => &imp.comment_tag,
             };
 
-            return_value = value;
+            let start = buffer.iter_at_line_index(line as i32, start as i32);
+            let end = buffer.iter_at_line_index(line as i32, text.len() as i32);
+
+            if let (Some(start), Some(end)) = (start, end) {
+                buffer.apply_tag(tag, &start, &end);
+            }
+        }
+    }
+
+    /// Follows the user's edits that move lines. Undo and redo put back the
+    /// references along with the rest of the text, so they're left alone, as
+    /// is text that's set all at once.
+    fn setup_line_references(&self) {
+        let buffer = &*self.imp().text_buffer;
+
+        buffer.connect_begin_user_action(glib::clone!(@weak self as obj => move |_| {
+            obj.imp().user_action.set(true);
+        }));
+
+        buffer.connect_end_user_action(glib::clone!(@weak self as obj => move |_| {
+            obj.imp().user_action.set(false);
+        }));
+
+        for signal in ["undo", "redo"] {
+            buffer.connect_local(signal, false, glib::clone!(@weak self as obj => @default-return None, move |_| {
+                obj.imp().undoing.set(true);
+                None
+            }));
+
+            buffer.connect_local(signal, true, glib::clone!(@weak self as obj => @default-return None, move |_| {
+                obj.imp().undoing.set(false);
+                None
+            }));
         }
 
-        return_value
+        buffer.connect_insert_text(glib::clone!(@weak self as obj => move |_, location, text| {
+            let lines = text.matches('\n').count() as i32;
+
+            // Typing in the middle of a line leaves that line where it is.
+            if lines > 0 && obj.is_user_edit() {
+                let first = location.line() + i32::from(!location.starts_line());
+                obj.imp().moved_lines.set(Some((first, lines)));
+
+                // The new text keeps its own references, up to the end of
+                // its last line, which can be partway into a line.
+                let last = text.rsplit('\n').next().unwrap_or_default().len() as i32;
+                let start = (location.line(), location.line_index());
+                obj.imp().inserted.set(Some((start, (start.0 + lines, last))));
+            }
+        }));
+
+        buffer.connect_delete_range(glib::clone!(@weak self as obj => move |_, start, end| {
+            let lines = end.line() - start.line();
+
+            if lines > 0 && obj.is_user_edit() {
+                let first = end.line() + i32::from(!start.starts_line());
+                obj.imp().moved_lines.set(Some((first, -lines)));
+                obj.imp().inserted.set(None);
+            }
+        }));
+    }
+
+    fn is_user_edit(&self) -> bool {
+        let imp = self.imp();
+
+        imp.user_action.get() && !imp.undoing.get() && !imp.renumbering.get()
+    }
+
+    /// Rewrites `lineN` references to lines from `first` (counting from 0)
+    /// on, which moved by `delta`. References to removed lines are left
+    /// alone, and so are those in the `inserted` text, which were written
+    /// for where it is now.
+    fn renumber_line_references(&self, first: i32, delta: i32, inserted: Option<(Position, Position)>) {
+        let imp = self.imp();
+        let buffer = &*imp.text_buffer;
+        let input = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+
+        // References count from 1, buffer lines from 0.
+        let moved = |number: usize| match number as i64 > first as i64 {
+            true => (number as i64 + delta as i64).max(1) as usize,
+            false => number,
+        };
+
+        let is_inserted = |line: usize, span: Span| match inserted {
+            Some((start, end)) => start <= (line as i32, span.start as i32) && (line as i32, span.end as i32) <= end,
+            None => false,
+        };
+
+        let edits: Vec<_> = input.split('\n')
+            .enumerate()
+            .flat_map(|(line, text)| references::renumber(text, moved).into_iter().map(move |edit| (line, edit)))
+            .filter(|(line, (span, _))| !is_inserted(*line, *span))
+            .collect();
+
+        imp.renumbering.set(true);
+
+        // Part of the user's edit, so one undo takes back both.
+        buffer.begin_user_action();
+
+        // Going backwards keeps the offsets of the earlier edits valid.
+        for (line, (span, text)) in edits.iter().rev() {
+            let start = buffer.iter_at_line_index(*line as i32, span.start as i32);
+            let end = buffer.iter_at_line_index(*line as i32, span.end as i32);
+
+            if let (Some(mut start), Some(mut end)) = (start, end) {
+                buffer.delete(&mut start, &mut end);
+                buffer.insert(&mut start, text);
+            }
+        }
+
+        buffer.end_user_action();
+        imp.renumbering.set(false);
+    }
+
+    /// Evaluates a line, carrying on past errors so a broken statement
+    /// doesn't take the rest of the line with it. The value is the last
+    /// statement's, if it has one.
+    fn evaluate(&self, input: &str, separators: Separators, compiled: &mut Cache, interpreter: &mut Interpreter, context: &mut Context) -> (Option<Value>, Vec<Error>) {
+        let (chunks, mut errors) = compiled.compile(input, separators, context);
+
+        let mut return_value = None;
+
+        for chunk in chunks.iter() {
+            return_value = match interpreter.run(chunk, context) {
+                Ok(value) => value,
+                Err(error) => {
+                    // Parse errors fail again when the code reaches them.
+                    if !errors.contains(&error) {
+                        errors.push(error);
+                    }
+
+                    None
+                }
+            };
+        }
+
+        (return_value, errors)
+    }
+
+    fn format(&self, value: Value, separators: Separators) -> String {
+        let precision = self.imp().settings.int("precision").max(0) as usize;
+
+        match value {
+            Value::Boolean(boolean) => boolean.to_string(),
+            Value::Number(number) => separators.format(&format!("{number:.precision$}")),
+            Value::Percentage(percentage) => separators.format(&format!("{percentage:.precision$}%")),
+            Value::Fraction(number, notation) => separators.format(&number.format_fraction(notation, precision)),
+            Value::Quantity(quantity) => separators.format(&format!("{quantity:.precision$}")),
+            Value::Duration(seconds) => separators.format(&date::format_duration(&seconds, precision)),
+            Value::Date(date) => date.to_string(),
+            Value::Time(time) => time.to_string(),
+            Value::Text(text) => text,
+        }
     }
 
     fn show_about_dialog(&self) {